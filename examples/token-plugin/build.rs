@@ -0,0 +1,15 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::env;
+
+fn main() {
+    // libcryptsetup loads the plugin symbols with the CRYPTSETUP_TOKEN_1.0
+    // symbol version, which must be declared in a version script.
+    println!(
+        "cargo:rustc-cdylib-link-arg=-Wl,--version-script={}/token.map",
+        env::var("CARGO_MANIFEST_DIR").expect("set by cargo")
+    );
+    println!("cargo:rerun-if-changed=token.map");
+}
@@ -0,0 +1,58 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Example external token plugin for the `rust-example` token type.
+//!
+//! The token stores the passphrase of its keyslots in plain text in the
+//! `passphrase` field of the token JSON. This is only useful to demonstrate
+//! the plugin interface and must not be used to protect real data.
+//!
+//! Install the library as `libcryptsetup-token-rust-example.so` in the
+//! libcryptsetup token directory.
+
+use libcryptsetup_rs::{
+    c_uint, consts::vals::CryptLogLevel, export_token_plugin, log, CryptDevice, LibcryptErr,
+    TokenHandler,
+};
+
+struct RustExampleToken;
+
+impl RustExampleToken {
+    fn passphrase(json: &serde_json::Value) -> Result<&str, LibcryptErr> {
+        json.get("passphrase")
+            .and_then(|p| p.as_str())
+            .ok_or(LibcryptErr::InvalidConversion)
+    }
+}
+
+impl TokenHandler for RustExampleToken {
+    const NAME: &'static str = "rust-example";
+
+    type UserData = ();
+
+    fn open(
+        device: &mut CryptDevice,
+        token: c_uint,
+        _: Option<&mut ()>,
+    ) -> Result<Box<[u8]>, LibcryptErr> {
+        let json = device.token_handle().json_get(token)?;
+        Self::passphrase(&json)
+            .map(|p| Box::from(p.as_bytes()))
+            .map_err(|_| LibcryptErr::InvalidArgument(Vec::new()))
+    }
+
+    fn validate(_: &mut CryptDevice, json: &serde_json::Value) -> Result<(), LibcryptErr> {
+        Self::passphrase(json).map(|_| ())
+    }
+
+    fn dump(_: &mut CryptDevice, json: &serde_json::Value) {
+        let len = Self::passphrase(json).map(|p| p.len()).unwrap_or(0);
+        let _ = log(
+            CryptLogLevel::Normal,
+            &format!("\tPassphrase:  {len} bytes\n"),
+        );
+    }
+}
+
+export_token_plugin!(RustExampleToken, "0.1.0");
@@ -72,3 +72,15 @@ const uint32_t crypt_pbkdf_iter_time_set = CRYPT_PBKDF_ITER_TIME_SET;
 const uint32_t crypt_pbkdf_no_benchmark = CRYPT_PBKDF_NO_BENCHMARK;
 
 const uint32_t crypt_wipe_no_direct_io = CRYPT_WIPE_NO_DIRECT_IO;
+
+#ifdef CRYPT_KC_TYPE_PASSPHRASE
+const int crypt_kc_type_passphrase = CRYPT_KC_TYPE_PASSPHRASE;
+const int crypt_kc_type_keyfile = CRYPT_KC_TYPE_KEYFILE;
+const int crypt_kc_type_token = CRYPT_KC_TYPE_TOKEN;
+const int crypt_kc_type_key = CRYPT_KC_TYPE_KEY;
+#endif
+#ifdef CRYPT_KC_TYPE_KEYRING
+const int crypt_kc_type_keyring = CRYPT_KC_TYPE_KEYRING;
+const int crypt_kc_type_vk_keyring = CRYPT_KC_TYPE_VK_KEYRING;
+const int crypt_kc_type_signed_key = CRYPT_KC_TYPE_SIGNED_KEY;
+#endif
@@ -6,6 +6,10 @@ use std::{path::Path, ptr};
 
 use libc::{c_int, c_uint};
 
+#[cfg(cryptsetup23supported)]
+use crate::consts::flags::CryptActivateVerity;
+#[cfg(cryptsetup26supported)]
+use crate::keyslot_context::CryptKeyslotContext;
 use crate::{
     consts::flags::{CryptActivate, CryptDeactivate},
     device::CryptDevice,
@@ -119,6 +123,42 @@ impl<'a> CryptActivationHandle<'a> {
         )))
     }
 
+    /// Activate Verity device using a root hash and an optional PKCS#7 signature
+    /// of the root hash.
+    ///
+    /// A value of `None` for the signature will activate the device without
+    /// kernel signature verification of the root hash. A value of `None` for the
+    /// name will only check the root hash and will not activate the device.
+    #[cfg(cryptsetup23supported)]
+    pub fn activate_by_signed_key(
+        &mut self,
+        name: Option<&str>,
+        root_hash: &[u8],
+        signature: Option<&[u8]>,
+        flags: CryptActivateVerity,
+    ) -> Result<(), LibcryptErr> {
+        let name_cstring_option = match name {
+            Some(n) => Some(to_cstring!(n)?),
+            None => None,
+        };
+        let (signature_ptr, signature_len) = match signature {
+            Some(s) => (to_byte_ptr!(s), s.len()),
+            None => (ptr::null(), 0),
+        };
+        errno!(mutex!(libcryptsetup_rs_sys::crypt_activate_by_signed_key(
+            self.reference.as_ptr(),
+            match name_cstring_option {
+                Some(ref cs) => cs.as_ptr(),
+                None => ptr::null_mut(),
+            },
+            to_byte_ptr!(root_hash),
+            root_hash.len(),
+            signature_ptr,
+            signature_len,
+            CryptActivate::from(flags).bits(),
+        )))
+    }
+
     /// Activeate device using passphrase in kernel keyring
     pub fn activate_by_keyring(
         &mut self,
@@ -147,6 +187,48 @@ impl<'a> CryptActivationHandle<'a> {
         .map(|k| k as c_uint)
     }
 
+    /// Activate device or check key using a keyslot context.
+    ///
+    /// A value of `None` for the name will only check the key and will not
+    /// activate the device. The additional keyslot and keyslot context are only
+    /// used to unlock the new volume key of a device in reencryption.
+    #[cfg(cryptsetup26supported)]
+    pub fn activate_by_keyslot_context(
+        &mut self,
+        name: Option<&str>,
+        keyslot: Option<c_uint>,
+        keyslot_context: &mut CryptKeyslotContext<'_>,
+        additional_keyslot: Option<c_uint>,
+        additional_keyslot_context: Option<&mut CryptKeyslotContext<'_>>,
+        flags: CryptActivate,
+    ) -> Result<c_uint, LibcryptErr> {
+        let name_cstring_option = match name {
+            Some(n) => Some(to_cstring!(n)?),
+            None => None,
+        };
+        errno_int_success!(mutex!(
+            libcryptsetup_rs_sys::crypt_activate_by_keyslot_context(
+                self.reference.as_ptr(),
+                match name_cstring_option {
+                    Some(ref cs) => cs.as_ptr(),
+                    None => ptr::null_mut(),
+                },
+                keyslot
+                    .map(|k| k as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_SLOT),
+                keyslot_context.as_ptr(),
+                additional_keyslot
+                    .map(|k| k as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_SLOT),
+                additional_keyslot_context
+                    .map(|kc| kc.as_ptr())
+                    .unwrap_or(ptr::null_mut()),
+                flags.bits(),
+            )
+        ))
+        .map(|k| k as c_uint)
+    }
+
     /// Deactivate crypt device
     pub fn deactivate(&mut self, name: &str, flags: CryptDeactivate) -> Result<(), LibcryptErr> {
         let name_cstring = to_cstring!(name)?;
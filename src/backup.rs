@@ -2,9 +2,279 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{path::Path, ptr};
+use std::{collections::BTreeMap, path::Path, ptr};
+#[cfg(cryptsetup23supported)]
+use std::{
+    env,
+    ffi::CString,
+    fs::{self, DirBuilder, File, OpenOptions, Permissions},
+    io::{self, Read, Write},
+    mem::MaybeUninit,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, PermissionsExt},
+        io::FromRawFd,
+    },
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use crate::{consts::vals::EncryptionFormat, device::CryptDevice, err::LibcryptErr};
+use libc::c_uint;
+use serde_json::Value;
+use uuid::Uuid;
+
+#[cfg(cryptsetup23supported)]
+use crate::mem::SafeMemHandle;
+use crate::{
+    consts::vals::{EncryptionFormat, KeyslotInfo},
+    device::{CryptDevice, CryptInit},
+    err::LibcryptErr,
+    keyslot::CryptKeyslotHandle,
+    luks1::Luks1Header,
+    luks2::{header::Luks2OnDiskHeader, token::CryptTokenInfo},
+};
+
+/// Header backup captured in memory that is safely zeroed when dropped
+#[cfg(cryptsetup23supported)]
+pub struct CryptHeaderBackup {
+    backup_mem: SafeMemHandle,
+}
+
+#[cfg(cryptsetup23supported)]
+impl AsRef<[u8]> for CryptHeaderBackup {
+    fn as_ref(&self) -> &[u8] {
+        self.backup_mem.as_ref()
+    }
+}
+
+/// Directories that are searched for a memory backed file system to hold
+/// the transient header backup file
+#[cfg(cryptsetup23supported)]
+fn memory_backed_dirs() -> impl Iterator<Item = PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(["/dev/shm", "/run"].iter().map(PathBuf::from))
+}
+
+#[cfg(cryptsetup23supported)]
+fn is_memory_backed(dir: &Path) -> bool {
+    let dir_cstring = match CString::new(dir.as_os_str().as_bytes()) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(dir_cstring.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    unsafe { stat.assume_init() }.f_type == libc::TMPFS_MAGIC
+}
+
+/// Private directory on a memory backed file system that is removed along
+/// with its contents when dropped
+#[cfg(cryptsetup23supported)]
+struct TransientDir(PathBuf);
+
+#[cfg(cryptsetup23supported)]
+impl TransientDir {
+    fn new() -> Result<Self, LibcryptErr> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let parent = memory_backed_dirs()
+            .find(|dir| is_memory_backed(dir))
+            .ok_or_else(|| {
+                LibcryptErr::Other(
+                    "No memory backed file system available for the header backup".to_string(),
+                )
+            })?;
+        let path = parent.join(format!(
+            "libcryptsetup-rs-backup-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&path)
+            .map_err(LibcryptErr::IOError)?;
+        Ok(TransientDir(path))
+    }
+}
+
+#[cfg(cryptsetup23supported)]
+impl Drop for TransientDir {
+    fn drop(&mut self) {
+        if let Ok(entries) = fs::read_dir(&self.0) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                // libcryptsetup creates backup files read-only.
+                let _ = fs::set_permissions(&path, Permissions::from_mode(0o600));
+                if let Ok(mut f) = OpenOptions::new().write(true).open(&path) {
+                    if let Ok(metadata) = f.metadata() {
+                        let _ = io::copy(&mut io::repeat(0).take(metadata.len()), &mut f);
+                    }
+                }
+                let _ = fs::remove_file(&path);
+            }
+        }
+        let _ = fs::remove_dir(&self.0);
+    }
+}
+
+/// Anonymous in-memory file holding a header backup for restoration
+#[cfg(cryptsetup23supported)]
+fn memfd() -> Result<(File, PathBuf), LibcryptErr> {
+    let fd = unsafe { libc::memfd_create(c"libcryptsetup-rs-backup".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(LibcryptErr::IOError(io::Error::last_os_error()));
+    }
+    Ok((
+        unsafe { File::from_raw_fd(fd) },
+        PathBuf::from(format!("/proc/self/fd/{fd}")),
+    ))
+}
+
+/// Differences between the header on a device and a header backup
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CryptHeaderDiff {
+    /// Whether the header on the device could be loaded. If not, every
+    /// keyslot and token in the backup is reported as added.
+    pub device_header_loaded: bool,
+    /// Keyslots only present in the backup
+    pub keyslots_added: Vec<c_uint>,
+    /// Keyslots only present on the device
+    pub keyslots_removed: Vec<c_uint>,
+    /// Keyslots present in both with a different key size or area
+    pub keyslots_changed: Vec<c_uint>,
+    /// LUKS2 tokens only present in the backup
+    pub tokens_added: Vec<c_uint>,
+    /// LUKS2 tokens only present on the device
+    pub tokens_removed: Vec<c_uint>,
+    /// LUKS2 tokens present in both with different JSON metadata
+    pub tokens_changed: Vec<c_uint>,
+}
+
+impl CryptHeaderDiff {
+    /// Check whether the backup has the same keyslots and tokens as the device
+    pub fn is_empty(&self) -> bool {
+        self.keyslots_added.is_empty()
+            && self.keyslots_removed.is_empty()
+            && self.keyslots_changed.is_empty()
+            && self.tokens_added.is_empty()
+            && self.tokens_removed.is_empty()
+            && self.tokens_changed.is_empty()
+    }
+
+    fn compare<T: PartialEq>(
+        live: &BTreeMap<c_uint, T>,
+        backup: &BTreeMap<c_uint, T>,
+        added: &mut Vec<c_uint>,
+        removed: &mut Vec<c_uint>,
+        changed: &mut Vec<c_uint>,
+    ) {
+        for (id, backup_value) in backup {
+            match live.get(id) {
+                Some(live_value) if live_value != backup_value => changed.push(*id),
+                Some(_) => (),
+                None => added.push(*id),
+            }
+        }
+        removed.extend(live.keys().filter(|id| !backup.contains_key(id)));
+    }
+
+    fn new(live: Option<&HeaderSummary>, backup: &HeaderSummary) -> Self {
+        let empty = HeaderSummary::default();
+        let mut diff = CryptHeaderDiff {
+            device_header_loaded: live.is_some(),
+            ..Default::default()
+        };
+        let live = live.unwrap_or(&empty);
+        Self::compare(
+            &live.keyslots,
+            &backup.keyslots,
+            &mut diff.keyslots_added,
+            &mut diff.keyslots_removed,
+            &mut diff.keyslots_changed,
+        );
+        Self::compare(
+            &live.tokens,
+            &backup.tokens,
+            &mut diff.tokens_added,
+            &mut diff.tokens_removed,
+            &mut diff.tokens_changed,
+        );
+        diff
+    }
+}
+
+/// Header properties of a loaded device used to validate a restore
+#[derive(Default)]
+struct HeaderSummary {
+    format: Option<EncryptionFormat>,
+    uuid: Option<Uuid>,
+    data_offset: u64,
+    /// Key size and keyslot area for each active keyslot
+    keyslots: BTreeMap<c_uint, (c_uint, (u64, u64))>,
+    tokens: BTreeMap<c_uint, Value>,
+}
+
+impl HeaderSummary {
+    fn new(device: &mut CryptDevice) -> Result<Self, LibcryptErr> {
+        let format = device.format_handle().get_type()?;
+        let max_keyslots = CryptKeyslotHandle::max_keyslots(device.format_handle().get_type()?)?;
+        let mut keyslots = BTreeMap::new();
+        for keyslot in 0..max_keyslots {
+            let mut handle = device.keyslot_handle();
+            if let KeyslotInfo::Active | KeyslotInfo::ActiveLast | KeyslotInfo::Unbound =
+                handle.status(keyslot)?
+            {
+                keyslots.insert(
+                    keyslot,
+                    (handle.get_key_size(keyslot)?, handle.area(keyslot)?),
+                );
+            }
+        }
+        let mut tokens = BTreeMap::new();
+        if format == EncryptionFormat::Luks2 {
+            for token in 0.. {
+                match device.token_handle().status(token)? {
+                    CryptTokenInfo::Invalid => break,
+                    CryptTokenInfo::Inactive => (),
+                    _ => {
+                        tokens.insert(token, device.token_handle().json_get(token)?);
+                    }
+                }
+            }
+        }
+        let mut status = device.status_handle();
+        Ok(HeaderSummary {
+            format: Some(format),
+            uuid: status.get_uuid().ok(),
+            data_offset: status.get_data_offset(),
+            keyslots,
+            tokens,
+        })
+    }
+}
+
+/// Recover the UUID from a LUKS header that libcryptsetup refuses to load
+fn damaged_header_uuid(device: &mut CryptDevice) -> Option<Uuid> {
+    let mut status = device.status_handle();
+    let path = match status.get_metadata_device_path().ok()? {
+        Some(path) => path.to_owned(),
+        None => status.get_device_path().ok()?.to_owned(),
+    };
+    let uuid = match Luks2OnDiskHeader::from_file(&path) {
+        Ok(header) => header
+            .newest()
+            .or(header.primary.as_ref())
+            .or(header.secondary.as_ref())?
+            .header
+            .uuid
+            .clone(),
+        Err(_) => Luks1Header::from_file(&path).ok()?.uuid,
+    };
+    Uuid::parse_str(&uuid).ok()
+}
 
 /// Handle for backup operations on a device
 pub struct CryptBackupHandle<'a> {
@@ -32,6 +302,48 @@ impl<'a> CryptBackupHandle<'a> {
         )))
     }
 
+    /// Back up header and keyslots to memory.
+    ///
+    /// libcryptsetup only writes backups to newly created files, so the backup
+    /// is written to a private directory on a memory backed file system
+    /// (`$XDG_RUNTIME_DIR`, `/dev/shm` or `/run`), read into safe memory and
+    /// overwritten and removed before this method returns. An error is
+    /// returned if none of these directories is memory backed.
+    #[cfg(cryptsetup23supported)]
+    pub fn header_backup_to_memory(
+        &mut self,
+        requested_type: Option<EncryptionFormat>,
+    ) -> Result<CryptHeaderBackup, LibcryptErr> {
+        let dir = TransientDir::new()?;
+        let backup_file = dir.0.join("header");
+        self.header_backup(requested_type, &backup_file)?;
+
+        let mut f = File::open(&backup_file).map_err(LibcryptErr::IOError)?;
+        let size = f.metadata().map_err(LibcryptErr::IOError)?.len() as usize;
+        let mut backup_mem = SafeMemHandle::alloc(size)?;
+        f.read_exact(backup_mem.as_mut())
+            .map_err(LibcryptErr::IOError)?;
+        Ok(CryptHeaderBackup { backup_mem })
+    }
+
+    /// Back up header and keyslots to a writer.
+    ///
+    /// See `header_backup_to_memory` for how the backup is captured.
+    #[cfg(cryptsetup23supported)]
+    pub fn header_backup_to_writer<W>(
+        &mut self,
+        requested_type: Option<EncryptionFormat>,
+        writer: &mut W,
+    ) -> Result<(), LibcryptErr>
+    where
+        W: Write,
+    {
+        let backup = self.header_backup_to_memory(requested_type)?;
+        writer
+            .write_all(backup.as_ref())
+            .map_err(LibcryptErr::IOError)
+    }
+
     /// Restore header and keyslots from a file
     pub fn header_restore(
         &mut self,
@@ -47,4 +359,168 @@ impl<'a> CryptBackupHandle<'a> {
             backup_file_cstring.as_ptr(),
         )))
     }
+
+    /// Restore header and keyslots from a file after checking that the backup
+    /// belongs to this device.
+    ///
+    /// The backup is loaded as a separate device first. If the header on the
+    /// device can be loaded, its format, UUID and data offset must match the
+    /// backup. Otherwise the UUID is recovered from any remaining LUKS1 or
+    /// LUKS2 binary header on the device and must match if one is found. If
+    /// no header is found at all, the backup cannot be matched to the device
+    /// and an error is returned unless `allow_unidentified` is set.
+    /// `confirm` then receives the keyslot and token differences and the
+    /// header is only restored if it returns `true`.
+    ///
+    /// Returns whether the header was restored.
+    pub fn header_restore_checked<F>(
+        &mut self,
+        requested_type: Option<EncryptionFormat>,
+        backup_file: &Path,
+        allow_unidentified: bool,
+        confirm: F,
+    ) -> Result<bool, LibcryptErr>
+    where
+        F: FnOnce(&CryptHeaderDiff) -> bool,
+    {
+        let mut backup_device = CryptInit::init(backup_file)?;
+        backup_device
+            .context_handle()
+            .load::<()>(requested_type, None)?;
+        let backup = HeaderSummary::new(&mut backup_device)?;
+
+        let live = match self.reference.context_handle().load::<()>(None, None) {
+            Ok(()) => Some(HeaderSummary::new(self.reference)?),
+            Err(_) => None,
+        };
+        let uuid = match live {
+            Some(ref live) => {
+                if live.format != backup.format {
+                    return Err(LibcryptErr::Other(format!(
+                        "Backup format {:?} does not match device format {:?}",
+                        backup.format, live.format
+                    )));
+                }
+                if live.data_offset != backup.data_offset {
+                    return Err(LibcryptErr::Other(format!(
+                        "Backup data offset {} does not match device data offset {}",
+                        backup.data_offset, live.data_offset
+                    )));
+                }
+                live.uuid
+            }
+            None => damaged_header_uuid(self.reference),
+        };
+        match (uuid, backup.uuid) {
+            (Some(uuid), Some(backup_uuid)) if uuid != backup_uuid => {
+                return Err(LibcryptErr::Other(format!(
+                    "Backup UUID {backup_uuid} does not match device UUID {uuid}"
+                )));
+            }
+            (None, _) if !allow_unidentified => {
+                return Err(LibcryptErr::Other(
+                    "No header found on the device to check the backup against".to_string(),
+                ));
+            }
+            _ => (),
+        }
+
+        if !confirm(&CryptHeaderDiff::new(live.as_ref(), &backup)) {
+            return Ok(false);
+        }
+        let requested_type = match backup.format {
+            Some(EncryptionFormat::Luks1) => Some(EncryptionFormat::Luks1),
+            Some(EncryptionFormat::Luks2) => Some(EncryptionFormat::Luks2),
+            _ => None,
+        };
+        self.header_restore(requested_type, backup_file)?;
+        Ok(true)
+    }
+
+    /// Restore header and keyslots from a reader.
+    ///
+    /// The backup is passed to libcryptsetup through an anonymous in-memory
+    /// file and never touches a file system.
+    #[cfg(cryptsetup23supported)]
+    pub fn header_restore_from_reader<R>(
+        &mut self,
+        requested_type: Option<EncryptionFormat>,
+        reader: &mut R,
+    ) -> Result<(), LibcryptErr>
+    where
+        R: Read,
+    {
+        let (mut f, path) = memfd()?;
+        let result = io::copy(reader, &mut f)
+            .map_err(LibcryptErr::IOError)
+            .and_then(|_| self.header_restore(requested_type, &path));
+        let _ = f.set_len(0);
+        result
+    }
+
+    /// Restore header and keyslots from a buffer.
+    ///
+    /// See `header_restore_from_reader` for how the backup is passed to
+    /// libcryptsetup.
+    #[cfg(cryptsetup23supported)]
+    pub fn header_restore_from_slice(
+        &mut self,
+        requested_type: Option<EncryptionFormat>,
+        backup: &[u8],
+    ) -> Result<(), LibcryptErr> {
+        self.header_restore_from_reader(requested_type, &mut &*backup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_header_diff() {
+        let live = HeaderSummary {
+            keyslots: [(0, (64, (32768, 258048))), (1, (64, (290816, 258048)))].into(),
+            tokens: [(0, json!({"type": "a", "keyslots": ["0"]}))].into(),
+            ..Default::default()
+        };
+        let backup = HeaderSummary {
+            keyslots: [(0, (64, (32768, 258048))), (2, (64, (548864, 258048)))].into(),
+            tokens: [(0, json!({"type": "a", "keyslots": ["2"]}))].into(),
+            ..Default::default()
+        };
+
+        let diff = CryptHeaderDiff::new(Some(&live), &backup);
+        assert!(diff.device_header_loaded);
+        assert_eq!(diff.keyslots_added, vec![2]);
+        assert_eq!(diff.keyslots_removed, vec![1]);
+        assert!(diff.keyslots_changed.is_empty());
+        assert!(diff.tokens_added.is_empty() && diff.tokens_removed.is_empty());
+        assert_eq!(diff.tokens_changed, vec![0]);
+        assert!(!diff.is_empty());
+        assert!(CryptHeaderDiff::new(Some(&backup), &backup).is_empty());
+
+        let diff = CryptHeaderDiff::new(None, &backup);
+        assert!(!diff.device_header_loaded);
+        assert_eq!(diff.keyslots_added, vec![0, 2]);
+        assert_eq!(diff.tokens_added, vec![0]);
+    }
+
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_transient_dir_wipes_read_only_files() {
+        let dir = TransientDir::new().unwrap();
+        let path = dir.0.join("backup");
+        fs::write(&path, b"header").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o400)).unwrap();
+        let mut reader = File::open(&path).unwrap();
+        let dir_path = dir.0.clone();
+        drop(dir);
+
+        assert!(!dir_path.exists());
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![0u8; 6]);
+    }
 }
@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    any::Any,
+    ffi::CStr,
+    ops::ControlFlow,
+    os::raw::{c_char, c_int, c_void},
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Boxed closure answering confirmation prompts, stored on the device
+pub(crate) type ConfirmFn = Box<dyn FnMut(&str) -> bool>;
+
+/// Progress closure for a single operation together with a panic raised by
+/// it, which is resumed once libcryptsetup has returned
+pub(crate) struct ProgressState<'a> {
+    progress: &'a mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<'a> ProgressState<'a> {
+    pub(crate) fn new(progress: &'a mut dyn FnMut(u64, u64) -> ControlFlow<()>) -> Self {
+        ProgressState {
+            progress,
+            panic: None,
+        }
+    }
+
+    pub(crate) fn as_ptr(&mut self) -> *mut c_void {
+        (self as *mut Self).cast::<c_void>()
+    }
+
+    /// Return the result of the operation, resuming a panic raised by the
+    /// closure
+    pub(crate) fn finish<T>(self, result: T) -> T {
+        if let Some(payload) = self.panic {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+}
+
+/// Progress trampoline for `ProgressState`. A panic or `ControlFlow::Break`
+/// aborts the operation.
+pub(crate) unsafe extern "C" fn progress_trampoline(
+    size: u64,
+    offset: u64,
+    usrptr: *mut c_void,
+) -> c_int {
+    let state = match usrptr.cast::<ProgressState<'_>>().as_mut() {
+        Some(state) => state,
+        None => return 1,
+    };
+    if state.panic.is_some() {
+        return 1;
+    }
+    match panic::catch_unwind(AssertUnwindSafe(|| (state.progress)(size, offset))) {
+        Ok(ControlFlow::Continue(())) => 0,
+        Ok(ControlFlow::Break(())) => 1,
+        Err(payload) => {
+            state.panic = Some(payload);
+            1
+        }
+    }
+}
+
+/// Confirmation trampoline for `ConfirmFn`. A panic answers the prompt with
+/// no, which aborts the operation.
+pub(crate) unsafe extern "C" fn confirm_trampoline(
+    msg: *const c_char,
+    usrptr: *mut c_void,
+) -> c_int {
+    let confirm = match usrptr.cast::<ConfirmFn>().as_mut() {
+        Some(confirm) => confirm,
+        None => return 0,
+    };
+    let msg = if msg.is_null() {
+        Default::default()
+    } else {
+        CStr::from_ptr(msg).to_string_lossy()
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| confirm(msg.trim_end()))).unwrap_or(false) as c_int
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_trampoline() {
+        let mut calls = Vec::new();
+        let mut progress = |size, offset| {
+            calls.push((size, offset));
+            if offset < size {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        };
+        let mut state = ProgressState::new(&mut progress);
+        unsafe {
+            assert_eq!(progress_trampoline(10, 5, state.as_ptr()), 0);
+            assert_eq!(progress_trampoline(10, 10, state.as_ptr()), 1);
+        }
+        assert_eq!(state.finish(()), ());
+        assert_eq!(calls, vec![(10, 5), (10, 10)]);
+    }
+
+    #[test]
+    fn test_progress_trampoline_panic() {
+        let mut calls = 0;
+        let mut progress = |_, _| {
+            calls += 1;
+            panic!("progress failed");
+        };
+        let mut state = ProgressState::new(&mut progress);
+        unsafe {
+            assert_eq!(progress_trampoline(10, 5, state.as_ptr()), 1);
+            // The closure is not called again after panicking.
+            assert_eq!(progress_trampoline(10, 6, state.as_ptr()), 1);
+        }
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| state.finish(()))).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"progress failed"));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_confirm_trampoline() {
+        let mut confirm: ConfirmFn = Box::new(|msg| msg == "Really proceed?");
+        let ptr = (&mut confirm as *mut ConfirmFn).cast::<c_void>();
+        unsafe {
+            assert_eq!(confirm_trampoline(c"Really proceed?\n".as_ptr(), ptr), 1);
+            assert_eq!(confirm_trampoline(c"Something else".as_ptr(), ptr), 0);
+        }
+
+        let mut confirm: ConfirmFn = Box::new(|_| panic!("confirm failed"));
+        let ptr = (&mut confirm as *mut ConfirmFn).cast::<c_void>();
+        assert_eq!(
+            unsafe { confirm_trampoline(c"Really proceed?".as_ptr(), ptr) },
+            0
+        );
+    }
+}
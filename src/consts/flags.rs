@@ -43,6 +43,25 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Subset of activation flags that apply to Verity devices.
+    pub struct CryptActivateVerity: u32 {
+        const READONLY = libcryptsetup_rs_sys::crypt_activate_readonly;
+        const IGNORE_CORRUPTION = libcryptsetup_rs_sys::crypt_activate_ignore_corruption;
+        const RESTART_ON_CORRUPTION = libcryptsetup_rs_sys::crypt_activate_restart_on_corruption;
+        const IGNORE_ZERO_BLOCKS = libcryptsetup_rs_sys::crypt_activate_ignore_zero_blocks;
+        const CHECK_AT_MOST_ONCE = libcryptsetup_rs_sys::crypt_activate_check_at_most_once;
+        #[cfg(cryptsetup24supported)]
+        const PANIC_ON_CORRUPTION = libcryptsetup_rs_sys::crypt_activate_panic_on_corruption;
+    }
+}
+
+impl From<CryptActivateVerity> for CryptActivate {
+    fn from(flags: CryptActivateVerity) -> Self {
+        CryptActivate::from_bits_retain(flags.bits())
+    }
+}
+
 bitflags! {
     /// Flags for crypt deactivate operations
     pub struct CryptDeactivate: u32 {
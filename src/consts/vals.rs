@@ -33,6 +33,12 @@ pub enum EncryptionFormat {
     Tcrypt,
     #[allow(missing_docs)]
     Integrity,
+    /// BitLocker compatible format; load and activation only
+    #[cfg(cryptsetup23supported)]
+    Bitlk,
+    /// Apple FileVault2 compatible format; load and activation only
+    #[cfg(cryptsetup26supported)]
+    Fvault2,
 }
 
 impl EncryptionFormat {
@@ -54,6 +60,12 @@ impl EncryptionFormat {
             EncryptionFormat::Integrity => libcryptsetup_rs_sys::CRYPT_INTEGRITY
                 .as_ptr()
                 .cast::<c_char>(),
+            #[cfg(cryptsetup23supported)]
+            EncryptionFormat::Bitlk => libcryptsetup_rs_sys::CRYPT_BITLK.as_ptr().cast::<c_char>(),
+            #[cfg(cryptsetup26supported)]
+            EncryptionFormat::Fvault2 => libcryptsetup_rs_sys::CRYPT_FVAULT2
+                .as_ptr()
+                .cast::<c_char>(),
         }
     }
 
@@ -75,6 +87,14 @@ impl EncryptionFormat {
         } else if libcryptsetup_rs_sys::CRYPT_INTEGRITY == p_bytes {
             Ok(EncryptionFormat::Integrity)
         } else {
+            #[cfg(cryptsetup23supported)]
+            if libcryptsetup_rs_sys::CRYPT_BITLK == p_bytes {
+                return Ok(EncryptionFormat::Bitlk);
+            }
+            #[cfg(cryptsetup26supported)]
+            if libcryptsetup_rs_sys::CRYPT_FVAULT2 == p_bytes {
+                return Ok(EncryptionFormat::Fvault2);
+            }
             Err(LibcryptErr::InvalidConversion)
         }
     }
@@ -132,6 +152,51 @@ impl TryFrom<c_int> for CryptLogLevel {
     }
 }
 
+/// Type of credential held by a keyslot context
+#[cfg(cryptsetup26supported)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CryptKeyslotContextType {
+    /// Passphrase
+    Passphrase,
+    /// Key file
+    Keyfile,
+    /// LUKS2 token
+    Token,
+    /// Volume key
+    Key,
+    /// Passphrase stored in the kernel keyring
+    #[cfg(cryptsetup27supported)]
+    Keyring,
+    /// Volume key stored in the kernel keyring
+    #[cfg(cryptsetup27supported)]
+    VkKeyring,
+    /// Volume key with a signature
+    #[cfg(cryptsetup27supported)]
+    SignedKey,
+}
+
+#[cfg(cryptsetup26supported)]
+impl TryFrom<c_int> for CryptKeyslotContextType {
+    type Error = LibcryptErr;
+
+    fn try_from(v: c_int) -> Result<Self, <Self as TryFrom<c_int>>::Error> {
+        let type_ = match v {
+            libcryptsetup_rs_sys::crypt_kc_type_passphrase => CryptKeyslotContextType::Passphrase,
+            libcryptsetup_rs_sys::crypt_kc_type_keyfile => CryptKeyslotContextType::Keyfile,
+            libcryptsetup_rs_sys::crypt_kc_type_token => CryptKeyslotContextType::Token,
+            libcryptsetup_rs_sys::crypt_kc_type_key => CryptKeyslotContextType::Key,
+            #[cfg(cryptsetup27supported)]
+            libcryptsetup_rs_sys::crypt_kc_type_keyring => CryptKeyslotContextType::Keyring,
+            #[cfg(cryptsetup27supported)]
+            libcryptsetup_rs_sys::crypt_kc_type_vk_keyring => CryptKeyslotContextType::VkKeyring,
+            #[cfg(cryptsetup27supported)]
+            libcryptsetup_rs_sys::crypt_kc_type_signed_key => CryptKeyslotContextType::SignedKey,
+            _ => return Err(LibcryptErr::InvalidConversion),
+        };
+        Ok(type_)
+    }
+}
+
 pub(crate) enum CryptFlagsType {
     Activation = libcryptsetup_rs_sys::crypt_flags_type_CRYPT_FLAGS_ACTIVATION as isize,
     Requirements = libcryptsetup_rs_sys::crypt_flags_type_CRYPT_FLAGS_REQUIREMENTS as isize,
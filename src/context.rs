@@ -2,8 +2,16 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{os::raw::c_int, path::Path, ptr};
+#[cfg(cryptsetup25supported)]
+use std::os::raw::c_void;
+use std::{
+    os::raw::{c_int, c_uint},
+    path::Path,
+    ptr,
+};
 
+#[cfg(cryptsetup27supported)]
+use crate::keyslot_context::CryptKeyslotContext;
 use crate::{
     consts::vals::EncryptionFormat, device::CryptDevice, err::LibcryptErr, format::CryptParams,
 };
@@ -212,4 +220,125 @@ impl<'a> CryptContextHandle<'a> {
             )
         ))
     }
+
+    /// Resume crypt device using a volume key
+    #[cfg(cryptsetup23supported)]
+    pub fn resume_by_volume_key(
+        &mut self,
+        name: &str,
+        volume_key: &[u8],
+    ) -> Result<(), LibcryptErr> {
+        let name_cstring = to_cstring!(name)?;
+        errno_int_success!(mutex!(libcryptsetup_rs_sys::crypt_resume_by_volume_key(
+            self.reference.as_ptr(),
+            name_cstring.as_ptr(),
+            to_byte_ptr!(volume_key),
+            volume_key.len(),
+        )))
+        .map(|_| ())
+    }
+
+    /// Resume crypt device using a token
+    ///
+    /// A value of `None` for the token will try all tokens.
+    #[cfg(cryptsetup25supported)]
+    pub fn resume_by_token<T>(
+        &mut self,
+        name: &str,
+        token: Option<c_uint>,
+        usrdata: Option<&mut T>,
+    ) -> Result<c_uint, LibcryptErr> {
+        self.resume_by_token_pin_internal(name, None, token, None, usrdata)
+    }
+
+    /// Resume crypt device using a token and PIN
+    ///
+    /// A value of `None` for the token will try all tokens and a value of `None`
+    /// for the type will try tokens of any type.
+    #[cfg(cryptsetup25supported)]
+    pub fn resume_by_token_pin<T>(
+        &mut self,
+        name: &str,
+        type_: Option<&str>,
+        token: Option<c_uint>,
+        pin: &[u8],
+        usrdata: Option<&mut T>,
+    ) -> Result<c_uint, LibcryptErr> {
+        self.resume_by_token_pin_internal(name, type_, token, Some(pin), usrdata)
+    }
+
+    #[cfg(cryptsetup25supported)]
+    fn resume_by_token_pin_internal<T>(
+        &mut self,
+        name: &str,
+        type_: Option<&str>,
+        token: Option<c_uint>,
+        pin: Option<&[u8]>,
+        usrdata: Option<&mut T>,
+    ) -> Result<c_uint, LibcryptErr> {
+        let name_cstring = to_cstring!(name)?;
+        let type_cstring_option = match type_ {
+            Some(t) => Some(to_cstring!(t)?),
+            None => None,
+        };
+        let (pin_ptr, pin_len) = match pin {
+            Some(p) => (to_byte_ptr!(p), p.len()),
+            None => (ptr::null(), 0),
+        };
+        let usrdata_ptr = match usrdata {
+            Some(reference) => (reference as *mut T).cast::<c_void>(),
+            None => ptr::null_mut(),
+        };
+        errno_int_success!(mutex!(libcryptsetup_rs_sys::crypt_resume_by_token_pin(
+            self.reference.as_ptr(),
+            name_cstring.as_ptr(),
+            // NOTE: Must keep as_ref to avoid use after free error.
+            type_cstring_option
+                .as_ref()
+                .map(|s| s.as_ptr())
+                .unwrap_or_else(ptr::null),
+            token
+                .map(|t| t as c_int)
+                .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_TOKEN),
+            pin_ptr,
+            pin_len,
+            usrdata_ptr,
+        )))
+        .map(|rc| rc as c_uint)
+    }
+
+    /// Resume crypt device using a passphrase in the kernel keyring
+    #[cfg(cryptsetup27supported)]
+    pub fn resume_by_keyring(
+        &mut self,
+        name: &str,
+        key_description: &str,
+        keyslot: Option<c_uint>,
+    ) -> Result<c_uint, LibcryptErr> {
+        let mut keyslot_context =
+            CryptKeyslotContext::init_by_keyring(self.reference, key_description)?;
+        self.resume_by_keyslot_context(name, keyslot, &mut keyslot_context)
+    }
+
+    /// Resume crypt device using a keyslot context
+    #[cfg(cryptsetup27supported)]
+    pub fn resume_by_keyslot_context(
+        &mut self,
+        name: &str,
+        keyslot: Option<c_uint>,
+        keyslot_context: &mut CryptKeyslotContext<'_>,
+    ) -> Result<c_uint, LibcryptErr> {
+        let name_cstring = to_cstring!(name)?;
+        errno_int_success!(mutex!(
+            libcryptsetup_rs_sys::crypt_resume_by_keyslot_context(
+                self.reference.as_ptr(),
+                name_cstring.as_ptr(),
+                keyslot
+                    .map(|k| k as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_SLOT),
+                keyslot_context.as_ptr(),
+            )
+        ))
+        .map(|k| k as c_uint)
+    }
 }
@@ -12,12 +12,16 @@ use libcryptsetup_rs_sys::crypt_device;
 use crate::{
     activate::CryptActivationHandle,
     backup::CryptBackupHandle,
+    callback::{confirm_trampoline, ConfirmFn},
+    consts::vals::{CryptLogLevel, EncryptionFormat},
     context::CryptContextHandle,
-    err::LibcryptErr,
+    err::{ErrnoContext, LibcryptErr},
     format::CryptFormatHandle,
+    integrity::CryptIntegrityHandle,
     key::CryptVolumeKeyHandle,
     keyfile::CryptKeyfileHandle,
     keyslot::CryptKeyslotHandle,
+    log::{capture_log_callback, log_bridge_callback, DeviceLog, DeviceLogTarget, LoggingCallback},
     luks2::{
         flags::CryptLuks2FlagsHandle, reencrypt::CryptLuks2ReencryptHandle,
         token::CryptLuks2TokenHandle,
@@ -25,6 +29,7 @@ use crate::{
     runtime::CryptRuntimeHandle,
     settings::CryptSettingsHandle,
     status::CryptDeviceStatusHandle,
+    verity::CryptVerityHandle,
     wipe::CryptWipeHandle,
 };
 
@@ -42,7 +47,7 @@ impl CryptInit {
             &mut cdevice as *mut *mut crypt_device,
             device_path_cstring.as_ptr(),
         )))?;
-        Ok(CryptDevice { ptr: cdevice })
+        Ok(CryptDevice::new(cdevice))
     }
 
     /// Initialize by device path or a header path and a data device path
@@ -66,7 +71,7 @@ impl CryptInit {
                 None => ptr::null(),
             },
         )))?;
-        Ok(CryptDevice { ptr: cdevice })
+        Ok(CryptDevice::new(cdevice))
     }
 
     /// Initialize by name and header device path
@@ -82,28 +87,149 @@ impl CryptInit {
             header_device_path_cstring = path_to_cstring!(path)?;
         }
 
-        errno!(mutex!(libcryptsetup_rs_sys::crypt_init_by_name_and_header(
-            &mut cdevice as *mut *mut crypt_device,
-            name_cstring.as_ptr(),
-            if header_device_path.is_some() {
-                header_device_path_cstring.as_ptr()
+        errno!(
+            mutex!(libcryptsetup_rs_sys::crypt_init_by_name_and_header(
+                &mut cdevice as *mut *mut crypt_device,
+                name_cstring.as_ptr(),
+                if header_device_path.is_some() {
+                    header_device_path_cstring.as_ptr()
+                } else {
+                    ptr::null()
+                },
+            )),
+            ErrnoContext::MappingLookup
+        )?;
+        Ok(CryptDevice::new(cdevice))
+    }
+}
+
+/// Builder for initializing a device with an optional detached header
+#[derive(Default)]
+pub struct CryptInitBuilder<'a> {
+    header_device: Option<&'a Path>,
+    data_device: Option<&'a Path>,
+    name: Option<&'a str>,
+    data_offset: Option<u64>,
+    expected_format: Option<EncryptionFormat>,
+}
+
+impl<'a> CryptInitBuilder<'a> {
+    /// Create a new builder
+    pub fn new() -> Self {
+        CryptInitBuilder::default()
+    }
+
+    /// Set the device containing the header. Unless a data device is set, it
+    /// also contains the data.
+    pub fn header_device(mut self, path: &'a Path) -> Self {
+        self.header_device = Some(path);
+        self
+    }
+
+    /// Set a data device separate from the header device, making the header
+    /// detached
+    pub fn data_device(mut self, path: &'a Path) -> Self {
+        self.data_device = Some(path);
+        self
+    }
+
+    /// Initialize from the active device with the given name. The header
+    /// device is optional in this case and only needed if the header is
+    /// detached.
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Set the offset in 512-byte sectors for the data section, used when
+    /// formatting a device with a detached header
+    pub fn data_offset(mut self, offset: u64) -> Self {
+        self.data_offset = Some(offset);
+        self
+    }
+
+    /// Load the header after initialization and fail if it is not of the
+    /// given format
+    pub fn expected_format(mut self, format: EncryptionFormat) -> Self {
+        self.expected_format = Some(format);
+        self
+    }
+
+    /// Initialize the device
+    pub fn build(self) -> Result<CryptDevice, LibcryptErr> {
+        let mut device = match (self.name, self.header_device, self.data_device) {
+            (Some(_), _, Some(_)) => {
+                return Err(LibcryptErr::Other(
+                    "The data device of an active device is taken from its mapping".to_string(),
+                ))
+            }
+            (Some(name), header_device, None) => {
+                CryptInit::init_by_name_and_header(name, header_device)?
+            }
+            (None, Some(header_device), None) => CryptInit::init(header_device)?,
+            (None, Some(header_device), Some(data_device)) => {
+                CryptInit::init_with_data_device(Either::Right((header_device, data_device)))?
+            }
+            (None, None, _) => {
+                return Err(LibcryptErr::Other(
+                    "Either a header device or a device name is required".to_string(),
+                ))
+            }
+        };
+        if let Some(offset) = self.data_offset {
+            device.set_data_offset(offset)?;
+        }
+        if let Some(format) = self.expected_format {
+            if self.name.is_some() {
+                // crypt_init_by_name() already loads the header.
+                let found = device.format_handle().get_type()?;
+                if found != format {
+                    return Err(LibcryptErr::Other(format!(
+                        "Expected format {format:?} but found {found:?}"
+                    )));
+                }
             } else {
-                ptr::null()
-            },
-        )))?;
-        Ok(CryptDevice { ptr: cdevice })
+                device.context_handle().load::<()>(Some(format), None)?;
+            }
+        }
+        Ok(device)
     }
 }
 
 /// Data type that is a handle for a crypt device
 pub struct CryptDevice {
     ptr: *mut crypt_device,
+    log: Option<Box<DeviceLog>>,
+    confirm: Option<Box<ConfirmFn>>,
 }
 
 impl CryptDevice {
+    /// Wrap a newly initialized device and capture its log messages for
+    /// error reporting
+    fn new(ptr: *mut crypt_device) -> Self {
+        let mut log = Box::new(DeviceLog {
+            device: ptr,
+            target: DeviceLogTarget::Global,
+        });
+        mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
+            ptr,
+            Some(capture_log_callback),
+            (&mut *log as *mut DeviceLog).cast::<c_void>(),
+        ));
+        CryptDevice {
+            ptr,
+            log: Some(log),
+            confirm: None,
+        }
+    }
+
     /// Reconstruct a `CryptDevice` object from a pointer
     pub fn from_ptr(ptr: *mut crypt_device) -> Self {
-        CryptDevice { ptr }
+        CryptDevice {
+            ptr,
+            log: None,
+            confirm: None,
+        }
     }
 
     /// Get a settings option handle
@@ -176,6 +302,16 @@ impl CryptDevice {
         CryptLuks2ReencryptHandle::new(self)
     }
 
+    /// Get crypt device Integrity option handle
+    pub fn integrity_handle(&mut self) -> CryptIntegrityHandle<'_> {
+        CryptIntegrityHandle::new(self)
+    }
+
+    /// Get crypt device Verity option handle
+    pub fn verity_handle(&mut self) -> CryptVerityHandle<'_> {
+        CryptVerityHandle::new(self)
+    }
+
     /// Set the callback that prompts the user to confirm an action
     pub fn set_confirm_callback<T>(
         &mut self,
@@ -189,7 +325,85 @@ impl CryptDevice {
                 Some(ud) => (ud as *mut T).cast::<c_void>(),
                 None => ptr::null_mut(),
             },
-        ))
+        ));
+        self.confirm = None;
+    }
+
+    /// Set a closure that prompts the user to confirm an action. The closure
+    /// receives the prompt and returns whether to proceed; a panic in the
+    /// closure is treated as a refusal.
+    pub fn set_confirm_fn<F>(&mut self, confirm: F)
+    where
+        F: FnMut(&str) -> bool + 'static,
+    {
+        let mut confirm: Box<ConfirmFn> = Box::new(Box::new(confirm));
+        mutex!(libcryptsetup_rs_sys::crypt_set_confirm_callback(
+            self.ptr,
+            Some(confirm_trampoline),
+            (&mut *confirm as *mut ConfirmFn).cast::<c_void>(),
+        ));
+        self.confirm = Some(confirm);
+    }
+
+    /// Set the callback to be executed on logging events for this device only.
+    /// Passing `None` restores forwarding to the global log callback.
+    pub fn set_log_callback<T>(
+        &mut self,
+        callback: Option<LoggingCallback>,
+        usrdata: Option<&mut T>,
+    ) {
+        let usrptr = match usrdata {
+            Some(ud) => (ud as *mut T).cast::<c_void>(),
+            None => ptr::null_mut(),
+        };
+        match self.log {
+            Some(ref mut log) => {
+                log.target = match callback {
+                    Some(cb) => DeviceLogTarget::Callback(cb, usrptr),
+                    None => DeviceLogTarget::Global,
+                }
+            }
+            None => mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
+                self.ptr, callback, usrptr,
+            )),
+        }
+    }
+
+    /// Forward log messages for this device to the `log` crate regardless of
+    /// the global log callback. See `install_log_bridge` for the mapping.
+    pub fn install_log_bridge(&mut self) {
+        match self.log {
+            Some(ref mut log) => log.target = DeviceLogTarget::Bridge,
+            None => mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
+                self.ptr,
+                Some(log_bridge_callback),
+                self.ptr.cast::<c_void>(),
+            )),
+        }
+    }
+
+    /// Set a closure to be executed on logging events for this device only.
+    /// Panics in the closure are caught and discarded.
+    pub fn set_log_fn<F>(&mut self, log_fn: F)
+    where
+        F: FnMut(CryptLogLevel, &str) + 'static,
+    {
+        let target = DeviceLogTarget::Closure(Box::new(log_fn));
+        match self.log {
+            Some(ref mut log) => log.target = target,
+            None => {
+                let mut log = Box::new(DeviceLog {
+                    device: self.ptr,
+                    target,
+                });
+                mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
+                    self.ptr,
+                    Some(capture_log_callback),
+                    (&mut *log as *mut DeviceLog).cast::<c_void>(),
+                ));
+                self.log = Some(log);
+            }
+        }
     }
 
     /// Set the device path for a data device
@@ -208,6 +422,15 @@ impl CryptDevice {
         )))
     }
 
+    /// Check whether the header is stored on a device separate from the data
+    #[cfg(cryptsetup24supported)]
+    pub fn header_is_detached(&mut self) -> Result<bool, LibcryptErr> {
+        errno_int_success!(mutex!(libcryptsetup_rs_sys::crypt_header_is_detached(
+            self.ptr
+        )))
+        .map(|detached| detached != 0)
+    }
+
     pub(crate) fn as_ptr(&mut self) -> *mut crypt_device {
         self.ptr
     }
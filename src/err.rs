@@ -7,11 +7,19 @@ use std::{
     ffi::NulError,
     fmt::{self, Display},
     io,
+    os::raw::c_int,
     str::Utf8Error,
 };
 
 #[derive(Debug)]
 /// Error returned from any libcryptsetup-rs function
+///
+/// Error codes returned by libcryptsetup are mapped to the variants below by
+/// errno, except where a call reports a condition with a code of its own;
+/// a missing active mapping is reported as `ENODEV` by lookups by name and
+/// is mapped to `NotFound`. The messages libcryptsetup logged during the
+/// failing call, available from `messages()`, describe the failure in the
+/// terms of that call.
 pub enum LibcryptErr {
     /// Wrapper for `io::Error`
     IOError(io::Error),
@@ -31,6 +39,99 @@ pub enum LibcryptErr {
     NoNull(&'static str),
     /// Custom message
     Other(String),
+    /// `EPERM`: for activation, keyslot, volume key and token calls no key
+    /// is available with the given passphrase, key file or token; other calls
+    /// use it for operations that are not permitted. Carries the messages
+    /// libcryptsetup logged for the device during the failing call.
+    NoKey(Vec<String>),
+    /// The requested keyslot, token or device does not exist (`ENOENT`), or
+    /// an active mapping looked up by name does not exist (`ENODEV`)
+    NotFound(Vec<String>),
+    /// The device is in use (`EBUSY`)
+    Busy(Vec<String>),
+    /// The token requires a PIN (`ENOANO`)
+    PinRequired(Vec<String>),
+    /// `EINVAL`: the arguments are invalid, or the operation is not
+    /// supported for the device or its current state
+    InvalidArgument(Vec<String>),
+    /// Any other error code returned by libcryptsetup
+    Errno(c_int, Vec<String>),
+    /// libcryptsetup returned a positive value where only zero or a negative
+    /// error code was expected
+    UnexpectedReturn(c_int),
+}
+
+/// Kind of libcryptsetup call an error code was returned from, for codes
+/// whose meaning depends on the call
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ErrnoContext {
+    /// Calls using the common meaning of each code
+    Generic,
+    /// Lookup of an active mapping by name, which reports a missing mapping
+    /// as `ENODEV`
+    MappingLookup,
+}
+
+impl LibcryptErr {
+    /// Create an error from a positive errno returned by libcryptsetup,
+    /// attaching the messages logged during the failing call
+    pub(crate) fn from_errno(errno: c_int) -> Self {
+        LibcryptErr::from_errno_in(errno, ErrnoContext::Generic)
+    }
+
+    /// Create an error from a positive errno returned by a libcryptsetup call
+    /// of the given kind
+    pub(crate) fn from_errno_in(errno: c_int, context: ErrnoContext) -> Self {
+        let messages = crate::log::take_captured_messages();
+        match errno {
+            libc::ENODEV if context == ErrnoContext::MappingLookup => {
+                LibcryptErr::NotFound(messages)
+            }
+            libc::EPERM => LibcryptErr::NoKey(messages),
+            libc::ENOENT => LibcryptErr::NotFound(messages),
+            libc::EBUSY => LibcryptErr::Busy(messages),
+            libc::ENOANO => LibcryptErr::PinRequired(messages),
+            libc::EINVAL => LibcryptErr::InvalidArgument(messages),
+            e => LibcryptErr::Errno(e, messages),
+        }
+    }
+
+    /// Get the errno corresponding to the error, if any. `NotFound` reports
+    /// `ENOENT` even when it was mapped from `ENODEV`.
+    pub fn errno(&self) -> Option<c_int> {
+        match *self {
+            LibcryptErr::IOError(ref e) => e.raw_os_error(),
+            LibcryptErr::NoKey(_) => Some(libc::EPERM),
+            LibcryptErr::NotFound(_) => Some(libc::ENOENT),
+            LibcryptErr::Busy(_) => Some(libc::EBUSY),
+            LibcryptErr::PinRequired(_) => Some(libc::ENOANO),
+            LibcryptErr::InvalidArgument(_) => Some(libc::EINVAL),
+            LibcryptErr::Errno(e, _) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Get the messages libcryptsetup logged for the device during the
+    /// failing call
+    pub fn messages(&self) -> &[String] {
+        match *self {
+            LibcryptErr::NoKey(ref m)
+            | LibcryptErr::NotFound(ref m)
+            | LibcryptErr::Busy(ref m)
+            | LibcryptErr::PinRequired(ref m)
+            | LibcryptErr::InvalidArgument(ref m)
+            | LibcryptErr::Errno(_, ref m) => m,
+            _ => &[],
+        }
+    }
+}
+
+fn write_messages(f: &mut fmt::Formatter<'_>, messages: &[String]) -> fmt::Result {
+    if messages.is_empty() {
+        Ok(())
+    } else {
+        write!(f, ": {}", messages.join("; "))
+    }
 }
 
 impl Display for LibcryptErr {
@@ -55,8 +156,92 @@ impl Display for LibcryptErr {
                 write!(f, "Static string {s} was not created with c_str!() macro")
             }
             LibcryptErr::Other(ref s) => write!(f, "Failed with error: {s}"),
+            LibcryptErr::NoKey(ref m) => {
+                write!(
+                    f,
+                    "No key available with this passphrase, key file or token"
+                )?;
+                write_messages(f, m)
+            }
+            LibcryptErr::NotFound(ref m) => {
+                write!(f, "No such keyslot, token or device")?;
+                write_messages(f, m)
+            }
+            LibcryptErr::Busy(ref m) => {
+                write!(f, "Device is in use")?;
+                write_messages(f, m)
+            }
+            LibcryptErr::PinRequired(ref m) => {
+                write!(f, "Token requires a PIN")?;
+                write_messages(f, m)
+            }
+            LibcryptErr::InvalidArgument(ref m) => {
+                write!(f, "Invalid argument")?;
+                write_messages(f, m)
+            }
+            LibcryptErr::Errno(e, ref m) => {
+                write!(f, "{}", io::Error::from_raw_os_error(e))?;
+                write_messages(f, m)
+            }
+            LibcryptErr::UnexpectedReturn(i) => {
+                write!(f, "Cryptsetup returned unexpected value {i}")
+            }
         }
     }
 }
 
 impl Error for LibcryptErr {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_errno() {
+        assert!(matches!(
+            LibcryptErr::from_errno(libc::EPERM),
+            LibcryptErr::NoKey(_)
+        ));
+        assert!(matches!(
+            LibcryptErr::from_errno(libc::ENOANO),
+            LibcryptErr::PinRequired(_)
+        ));
+        for errno in [
+            libc::EPERM,
+            libc::ENOENT,
+            libc::EBUSY,
+            libc::ENOANO,
+            libc::EINVAL,
+            libc::ENODEV,
+        ] {
+            assert_eq!(LibcryptErr::from_errno(errno).errno(), Some(errno));
+        }
+        assert!(matches!(
+            LibcryptErr::from_errno_in(libc::ENODEV, ErrnoContext::MappingLookup),
+            LibcryptErr::NotFound(_)
+        ));
+        assert!(matches!(
+            LibcryptErr::from_errno_in(libc::EPERM, ErrnoContext::MappingLookup),
+            LibcryptErr::NoKey(_)
+        ));
+        assert_eq!(LibcryptErr::UnexpectedReturn(1).errno(), None);
+    }
+
+    #[test]
+    fn test_display_messages() {
+        let err = LibcryptErr::NoKey(vec![
+            "No key available with this passphrase.".to_string(),
+            "Keyslot open failed.".to_string(),
+        ]);
+        assert_eq!(err.messages().len(), 2);
+        assert_eq!(
+            err.to_string(),
+            "No key available with this passphrase, key file or token: \
+             No key available with this passphrase.; Keyslot open failed."
+        );
+        assert_eq!(
+            LibcryptErr::Busy(Vec::new()).to_string(),
+            "Device is in use"
+        );
+    }
+}
@@ -381,6 +381,24 @@ impl<'a> TryInto<CryptParamsVerityRef<'a>> for &'a CryptParamsVerity {
     }
 }
 
+impl CryptParamsVerityRef<'_> {
+    /// Let libcryptsetup generate a random salt of the given size on format.
+    pub(crate) fn generate_salt(&mut self, salt_size: u32) {
+        self.inner.salt = ptr::null();
+        self.inner.salt_size = salt_size;
+    }
+
+    /// Set additional Verity flags passed to libcryptsetup.
+    pub(crate) fn insert_flags(&mut self, flags: CryptVerity) {
+        self.inner.flags |= flags.bits();
+    }
+
+    /// Clear Verity flags passed to libcryptsetup.
+    pub(crate) fn remove_flags(&mut self, flags: CryptVerity) {
+        self.inner.flags &= !flags.bits();
+    }
+}
+
 impl CryptParams for CryptParamsVerityRef<'_> {
     fn as_ptr(&mut self) -> *mut c_void {
         (&mut self.inner as *mut crypt_params_verity).cast::<c_void>()
@@ -450,20 +468,27 @@ pub struct CryptParamsIntegrityRef<'a> {
     #[allow(dead_code)]
     reference: &'a CryptParamsIntegrity,
     #[allow(dead_code)]
-    integrity_cstring: CString,
+    integrity_cstring: Option<CString>,
     #[allow(dead_code)]
-    journal_integrity_cstring: CString,
+    journal_integrity_cstring: Option<CString>,
     #[allow(dead_code)]
-    journal_crypt_cstring: CString,
+    journal_crypt_cstring: Option<CString>,
 }
 
 /// Parameters for integrity checking
+///
+/// Empty algorithm names and keys are passed to libcryptsetup as null pointers
+/// so that the defaults are used. libcryptsetup never returns the journal keys
+/// of a loaded device so they are always empty when these parameters are read
+/// back from a device.
 pub struct CryptParamsIntegrity {
     #[allow(missing_docs)]
     pub journal_size: u64,
-    #[allow(missing_docs)]
+    /// Journal watermark in percent or, in bitmap mode, the number of sectors
+    /// per bitmap bit
     pub journal_watermark: c_uint,
-    #[allow(missing_docs)]
+    /// Journal commit time or, in bitmap mode, the bitmap flush time in
+    /// milliseconds
     pub journal_commit_time: c_uint,
     #[allow(missing_docs)]
     pub interleave_sectors: u32,
@@ -491,9 +516,23 @@ impl<'a> TryInto<CryptParamsIntegrityRef<'a>> for &'a CryptParamsIntegrity {
     type Error = LibcryptErr;
 
     fn try_into(self) -> Result<CryptParamsIntegrityRef<'a>, Self::Error> {
-        let integrity_cstring = to_cstring!(self.integrity)?;
-        let journal_integrity_cstring = to_cstring!(self.journal_integrity)?;
-        let journal_crypt_cstring = to_cstring!(self.journal_crypt)?;
+        let optional_cstring = |s: &str| -> Result<Option<CString>, LibcryptErr> {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(to_cstring!(s)?))
+            }
+        };
+        let optional_key = |k: &[u8]| {
+            if k.is_empty() {
+                ptr::null()
+            } else {
+                to_byte_ptr!(k)
+            }
+        };
+        let integrity_cstring = optional_cstring(&self.integrity)?;
+        let journal_integrity_cstring = optional_cstring(&self.journal_integrity)?;
+        let journal_crypt_cstring = optional_cstring(&self.journal_crypt)?;
         let inner = libcryptsetup_rs_sys::crypt_params_integrity {
             journal_size: self.journal_size,
             journal_watermark: self.journal_watermark,
@@ -502,13 +541,22 @@ impl<'a> TryInto<CryptParamsIntegrityRef<'a>> for &'a CryptParamsIntegrity {
             tag_size: self.tag_size,
             sector_size: self.sector_size,
             buffer_sectors: self.buffer_sectors,
-            integrity: integrity_cstring.as_ptr(),
+            integrity: integrity_cstring
+                .as_ref()
+                .map(|cs| cs.as_ptr())
+                .unwrap_or_else(ptr::null),
             integrity_key_size: self.integrity_key_size,
-            journal_integrity: journal_integrity_cstring.as_ptr(),
-            journal_integrity_key: to_byte_ptr!(self.journal_integrity_key),
+            journal_integrity: journal_integrity_cstring
+                .as_ref()
+                .map(|cs| cs.as_ptr())
+                .unwrap_or_else(ptr::null),
+            journal_integrity_key: optional_key(&self.journal_integrity_key),
             journal_integrity_key_size: self.journal_integrity_key.len() as u32,
-            journal_crypt: journal_crypt_cstring.as_ptr(),
-            journal_crypt_key: to_byte_ptr!(self.journal_crypt_key),
+            journal_crypt: journal_crypt_cstring
+                .as_ref()
+                .map(|cs| cs.as_ptr())
+                .unwrap_or_else(ptr::null),
+            journal_crypt_key: optional_key(&self.journal_crypt_key),
             journal_crypt_key_size: self.journal_crypt_key.len() as u32,
         };
         Ok(CryptParamsIntegrityRef {
@@ -525,6 +573,16 @@ impl<'a> TryFrom<&'a libcryptsetup_rs_sys::crypt_params_integrity> for CryptPara
     type Error = LibcryptErr;
 
     fn try_from(v: &'a libcryptsetup_rs_sys::crypt_params_integrity) -> Result<Self, Self::Error> {
+        let optional_string = |p: *const libc::c_char| match ptr_to_option!(p) {
+            Some(p) => from_str_ptr_to_owned!(p),
+            None => Ok(String::new()),
+        };
+        let optional_key = |p: *const libc::c_char, size: u32| match ptr_to_option!(p) {
+            Some(p) => {
+                Vec::from(unsafe { std::slice::from_raw_parts(p.cast::<u8>(), size as usize) })
+            }
+            None => Vec::new(),
+        };
         Ok(CryptParamsIntegrity {
             journal_size: v.journal_size,
             journal_watermark: v.journal_watermark,
@@ -533,22 +591,15 @@ impl<'a> TryFrom<&'a libcryptsetup_rs_sys::crypt_params_integrity> for CryptPara
             tag_size: v.tag_size,
             sector_size: v.sector_size,
             buffer_sectors: v.buffer_sectors,
-            integrity: from_str_ptr_to_owned!(v.integrity)?,
+            integrity: optional_string(v.integrity)?,
             integrity_key_size: v.integrity_key_size,
-            journal_integrity: from_str_ptr_to_owned!(v.journal_integrity)?,
-            journal_integrity_key: Vec::from(unsafe {
-                std::slice::from_raw_parts(
-                    v.journal_integrity_key.cast::<u8>(),
-                    v.journal_integrity_key_size as usize,
-                )
-            }),
-            journal_crypt: from_str_ptr_to_owned!(v.journal_crypt)?,
-            journal_crypt_key: Vec::from(unsafe {
-                std::slice::from_raw_parts(
-                    v.journal_crypt_key.cast::<u8>(),
-                    v.journal_crypt_key_size as usize,
-                )
-            }),
+            journal_integrity: optional_string(v.journal_integrity)?,
+            journal_integrity_key: optional_key(
+                v.journal_integrity_key,
+                v.journal_integrity_key_size,
+            ),
+            journal_crypt: optional_string(v.journal_crypt)?,
+            journal_crypt_key: optional_key(v.journal_crypt_key, v.journal_crypt_key_size),
         })
     }
 }
@@ -633,14 +684,17 @@ pub struct CryptParamsTcryptRef<'a> {
     #[allow(dead_code)]
     keyfiles_ptrs: Vec<*const libc::c_char>,
     #[allow(dead_code)]
-    hash_name_cstring: CString,
+    hash_name_cstring: Option<CString>,
     #[allow(dead_code)]
-    cipher_cstring: CString,
+    cipher_cstring: Option<CString>,
     #[allow(dead_code)]
-    mode_cstring: CString,
+    mode_cstring: Option<CString>,
 }
 
 /// Parameters for tcrypt operations
+///
+/// Empty hash, cipher and mode names are passed to libcryptsetup as null
+/// pointers so that all supported algorithms are tried when loading a header.
 pub struct CryptParamsTcrypt {
     #[allow(missing_docs)]
     pub passphrase: Option<Vec<u8>>,
@@ -672,9 +726,16 @@ impl<'a> TryInto<CryptParamsTcryptRef<'a>> for &'a CryptParamsTcrypt {
         }
         let mut keyfiles_ptrs: Vec<*const libc::c_char> =
             keyfiles_cstrings.iter().map(|cs| cs.as_ptr()).collect();
-        let hash_name_cstring = to_cstring!(self.hash_name)?;
-        let cipher_cstring = to_cstring!(self.cipher)?;
-        let mode_cstring = to_cstring!(self.mode)?;
+        let optional_cstring = |s: &str| -> Result<Option<CString>, LibcryptErr> {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(to_cstring!(s)?))
+            }
+        };
+        let hash_name_cstring = optional_cstring(&self.hash_name)?;
+        let cipher_cstring = optional_cstring(&self.cipher)?;
+        let mode_cstring = optional_cstring(&self.mode)?;
         Ok(CryptParamsTcryptRef {
             inner: libcryptsetup_rs_sys::crypt_params_tcrypt {
                 passphrase: match self.passphrase {
@@ -687,9 +748,18 @@ impl<'a> TryInto<CryptParamsTcryptRef<'a>> for &'a CryptParamsTcrypt {
                 },
                 keyfiles: keyfiles_ptrs.as_mut_ptr(),
                 keyfiles_count: keyfiles_cstrings.len() as u32,
-                hash_name: hash_name_cstring.as_ptr(),
-                cipher: cipher_cstring.as_ptr(),
-                mode: mode_cstring.as_ptr(),
+                hash_name: hash_name_cstring
+                    .as_ref()
+                    .map(|cs| cs.as_ptr())
+                    .unwrap_or_else(ptr::null),
+                cipher: cipher_cstring
+                    .as_ref()
+                    .map(|cs| cs.as_ptr())
+                    .unwrap_or_else(ptr::null),
+                mode: mode_cstring
+                    .as_ref()
+                    .map(|cs| cs.as_ptr())
+                    .unwrap_or_else(ptr::null),
                 flags: self.flags.bits(),
                 key_size: self.key_size,
                 veracrypt_pim: self.veracrypt_pim,
@@ -786,6 +856,10 @@ mod test {
             EncryptionFormat::Loopaes,
             EncryptionFormat::Luks1,
             EncryptionFormat::Plain,
+            #[cfg(cryptsetup23supported)]
+            EncryptionFormat::Bitlk,
+            #[cfg(cryptsetup26supported)]
+            EncryptionFormat::Fvault2,
         ] {
             assert_eq!(
                 EncryptionFormat::from_ptr(format.as_ptr()).unwrap(),
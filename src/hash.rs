@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use digest::DynDigest;
+
+/// Hash algorithms implemented in Rust, named as in libcryptsetup and the
+/// kernel crypto API
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum HashAlgorithm {
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha3_224,
+    Sha3_256,
+    Sha3_384,
+    Sha3_512,
+    Ripemd160,
+}
+
+impl HashAlgorithm {
+    const ALL: [HashAlgorithm; 10] = [
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Sha224,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha384,
+        HashAlgorithm::Sha512,
+        HashAlgorithm::Sha3_224,
+        HashAlgorithm::Sha3_256,
+        HashAlgorithm::Sha3_384,
+        HashAlgorithm::Sha3_512,
+        HashAlgorithm::Ripemd160,
+    ];
+
+    /// Look up a hash algorithm by name, ignoring case
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        HashAlgorithm::ALL
+            .into_iter()
+            .find(|alg| alg.name().eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha224 => "sha224",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha3_224 => "sha3-224",
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Sha3_384 => "sha3-384",
+            HashAlgorithm::Sha3_512 => "sha3-512",
+            HashAlgorithm::Ripemd160 => "ripemd160",
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            HashAlgorithm::Sha1 => Box::<sha1::Sha1>::default(),
+            HashAlgorithm::Sha224 => Box::<sha2::Sha224>::default(),
+            HashAlgorithm::Sha256 => Box::<sha2::Sha256>::default(),
+            HashAlgorithm::Sha384 => Box::<sha2::Sha384>::default(),
+            HashAlgorithm::Sha512 => Box::<sha2::Sha512>::default(),
+            HashAlgorithm::Sha3_224 => Box::<sha3::Sha3_224>::default(),
+            HashAlgorithm::Sha3_256 => Box::<sha3::Sha3_256>::default(),
+            HashAlgorithm::Sha3_384 => Box::<sha3::Sha3_384>::default(),
+            HashAlgorithm::Sha3_512 => Box::<sha3::Sha3_512>::default(),
+            HashAlgorithm::Ripemd160 => Box::<ripemd::Ripemd160>::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_algorithm_from_name() {
+        for alg in HashAlgorithm::ALL {
+            assert_eq!(HashAlgorithm::from_name(alg.name()), Some(alg));
+        }
+        assert_eq!(
+            HashAlgorithm::from_name("SHA256"),
+            Some(HashAlgorithm::Sha256)
+        );
+        assert_eq!(HashAlgorithm::from_name("whirlpool"), None);
+        assert_eq!(HashAlgorithm::Sha256.hasher().output_size(), 32);
+    }
+}
@@ -0,0 +1,155 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::{
+    consts::{flags::CryptActivate, vals::EncryptionFormat},
+    device::CryptDevice,
+    err::LibcryptErr,
+    format::{CryptParamsIntegrity, CryptParamsIntegrityRef},
+    Either,
+};
+
+/// Write mode of a standalone Integrity device
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CryptIntegrityMode {
+    /// Writes are committed through the journal
+    Journal,
+    /// Writes bypass the journal
+    Direct,
+    /// Writes bypass the journal and dirty regions are tracked in a bitmap.
+    ///
+    /// The number of sectors per bitmap bit and the bitmap flush time are
+    /// taken from the `journal_watermark` and `journal_commit_time` fields of
+    /// the parameters used to format or load the device.
+    Bitmap,
+}
+
+impl CryptIntegrityMode {
+    fn flags(self) -> CryptActivate {
+        match self {
+            CryptIntegrityMode::Journal => CryptActivate::empty(),
+            CryptIntegrityMode::Direct => CryptActivate::NO_JOURNAL,
+            CryptIntegrityMode::Bitmap => CryptActivate::NO_JOURNAL_BITMAP,
+        }
+    }
+
+    fn from_flags(flags: &CryptActivate) -> Self {
+        if flags.contains(CryptActivate::NO_JOURNAL_BITMAP) {
+            CryptIntegrityMode::Bitmap
+        } else if flags.contains(CryptActivate::NO_JOURNAL) {
+            CryptIntegrityMode::Direct
+        } else {
+            CryptIntegrityMode::Journal
+        }
+    }
+}
+
+/// Settings and runtime state of an active standalone Integrity device
+pub struct CryptIntegrityReport {
+    /// Integrity parameters of the device; the journal keys are never
+    /// exposed by libcryptsetup and are always empty
+    pub params: CryptParamsIntegrity,
+    /// Size in bytes of the journal integrity key
+    pub journal_integrity_key_size: u32,
+    /// Size in bytes of the journal encryption key
+    pub journal_crypt_key_size: u32,
+    /// Write mode of the active device
+    pub mode: CryptIntegrityMode,
+    /// Flags of the active device
+    pub flags: CryptActivate,
+    /// Number of integrity failures detected since activation
+    pub integrity_failures: u64,
+}
+
+/// Handle for standalone Integrity operations
+pub struct CryptIntegrityHandle<'a> {
+    reference: &'a mut CryptDevice,
+}
+
+impl<'a> CryptIntegrityHandle<'a> {
+    pub(crate) fn new(reference: &'a mut CryptDevice) -> Self {
+        CryptIntegrityHandle { reference }
+    }
+
+    /// Format the device as a standalone Integrity device.
+    ///
+    /// `integrity_key` is required for keyed integrity algorithms such as
+    /// `hmac(sha256)` and must match `integrity_key_size` in `params`. The
+    /// journal keys in `params` are kept by this context so the device can be
+    /// activated without loading it again. The integrity tags are not
+    /// initialized; wipe the activated device to avoid integrity failures on
+    /// reads of sectors that were never written.
+    pub fn format(
+        &mut self,
+        params: &CryptParamsIntegrity,
+        integrity_key: Option<&[u8]>,
+    ) -> Result<(), LibcryptErr> {
+        let mut params_ref: CryptParamsIntegrityRef<'_> = params.try_into()?;
+        self.reference.context_handle().format(
+            EncryptionFormat::Integrity,
+            ("", ""),
+            None,
+            match integrity_key {
+                Some(key) => Either::Left(key),
+                None => Either::Right(0),
+            },
+            Some(&mut params_ref),
+        )
+    }
+
+    /// Load the Integrity superblock of the device.
+    ///
+    /// Only the activation settings in `params` are used: the journal keys, the
+    /// journal watermark, the journal commit time and the buffer sectors.
+    pub fn load(&mut self, params: Option<&CryptParamsIntegrity>) -> Result<(), LibcryptErr> {
+        match params {
+            Some(p) => {
+                let mut params_ref: CryptParamsIntegrityRef<'_> = p.try_into()?;
+                self.reference
+                    .context_handle()
+                    .load(Some(EncryptionFormat::Integrity), Some(&mut params_ref))
+            }
+            None => self
+                .reference
+                .context_handle()
+                .load::<CryptParamsIntegrityRef<'_>>(Some(EncryptionFormat::Integrity), None),
+        }
+    }
+
+    /// Activate the device in the given write mode.
+    ///
+    /// Pass `CryptActivate::RECALCULATE` in `flags` to recalculate the integrity
+    /// tags in the background after activation.
+    pub fn activate(
+        &mut self,
+        name: &str,
+        integrity_key: Option<&[u8]>,
+        mode: CryptIntegrityMode,
+        flags: CryptActivate,
+    ) -> Result<(), LibcryptErr> {
+        self.reference.activate_handle().activate_by_volume_key(
+            Some(name),
+            integrity_key,
+            flags | mode.flags(),
+        )
+    }
+
+    /// Collect the Integrity settings of this context together with the flags
+    /// and the integrity failure counter of the active device `name`.
+    pub fn report(&mut self, name: &str) -> Result<CryptIntegrityReport, LibcryptErr> {
+        let raw = self.reference.status_handle().get_raw_integrity_info()?;
+        let params = CryptParamsIntegrity::try_from(&raw)?;
+        let mut runtime = self.reference.runtime_handle(name);
+        let flags = runtime.get_active_device()?.flags;
+        let integrity_failures = runtime.get_active_integrity_failures()?;
+        Ok(CryptIntegrityReport {
+            params,
+            journal_integrity_key_size: raw.journal_integrity_key_size,
+            journal_crypt_key_size: raw.journal_crypt_key_size,
+            mode: CryptIntegrityMode::from_flags(&flags),
+            flags,
+            integrity_failures,
+        })
+    }
+}
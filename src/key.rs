@@ -7,6 +7,8 @@ use std::{
     ptr,
 };
 
+#[cfg(cryptsetup26supported)]
+use crate::keyslot_context::CryptKeyslotContext;
 use crate::{device::CryptDevice, err::LibcryptErr};
 
 /// Handle for volume key operations
@@ -44,6 +46,30 @@ impl<'a> CryptVolumeKeyHandle<'a> {
         .map(|i| (i, volume_key_size_t))
     }
 
+    /// Get volume key from crypt device by unlocking a keyslot with a keyslot
+    /// context - first tuple element is key slot, second is volume key size
+    #[cfg(cryptsetup26supported)]
+    pub fn get_by_keyslot_context(
+        &mut self,
+        keyslot: Option<c_uint>,
+        volume_key: &mut [u8],
+        keyslot_context: &mut CryptKeyslotContext<'_>,
+    ) -> Result<(c_int, crate::size_t), LibcryptErr> {
+        let mut volume_key_size_t = volume_key.len();
+        errno_int_success!(mutex!(
+            libcryptsetup_rs_sys::crypt_volume_key_get_by_keyslot_context(
+                self.reference.as_ptr(),
+                keyslot
+                    .map(|i| i as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_SLOT),
+                to_mut_byte_ptr!(volume_key),
+                &mut volume_key_size_t as *mut _,
+                keyslot_context.as_ptr(),
+            )
+        ))
+        .map(|i| (i, volume_key_size_t))
+    }
+
     /// Verify that volume key is valid for crypt device
     pub fn verify(&mut self, volume_key: &[u8]) -> Result<(), LibcryptErr> {
         errno!(mutex!(libcryptsetup_rs_sys::crypt_volume_key_verify(
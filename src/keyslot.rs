@@ -10,6 +10,8 @@ use std::{
 use either::Either;
 use libc::{c_int, c_uint};
 
+#[cfg(cryptsetup26supported)]
+use crate::keyslot_context::CryptKeyslotContext;
 use crate::{
     consts::{
         flags::CryptVolumeKey,
@@ -135,6 +137,37 @@ impl<'a> CryptKeyslotHandle<'a> {
         .map(|k| k as c_uint)
     }
 
+    /// Add key slot using a keyslot context to unlock the volume key and a
+    /// keyslot context holding the new credential.
+    ///
+    /// Unlike `add_by_key` and `add_by_passphrase`, any type of credential can be
+    /// used to unlock the existing keyslot.
+    #[cfg(cryptsetup26supported)]
+    pub fn add_by_keyslot_context(
+        &mut self,
+        keyslot_existing: Option<c_uint>,
+        keyslot_context: &mut CryptKeyslotContext<'_>,
+        keyslot_new: Option<c_uint>,
+        new_keyslot_context: &mut CryptKeyslotContext<'_>,
+        flags: CryptVolumeKey,
+    ) -> Result<c_uint, LibcryptErr> {
+        errno_int_success!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_add_by_keyslot_context(
+                self.reference.as_ptr(),
+                keyslot_existing
+                    .map(|k| k as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_SLOT),
+                keyslot_context.as_ptr(),
+                keyslot_new
+                    .map(|k| k as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_SLOT),
+                new_keyslot_context.as_ptr(),
+                flags.bits(),
+            )
+        ))
+        .map(|k| k as c_uint)
+    }
+
     /// Destroy key slot
     pub fn destroy(&mut self, keyslot: c_uint) -> Result<(), LibcryptErr> {
         errno!(mutex!(libcryptsetup_rs_sys::crypt_keyslot_destroy(
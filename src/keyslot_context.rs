@@ -0,0 +1,231 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{ffi::CString, marker::PhantomData, path::Path, ptr};
+
+use libc::{c_int, c_uint, c_void};
+
+use libcryptsetup_rs_sys::crypt_keyslot_context;
+
+use crate::{consts::vals::CryptKeyslotContextType, device::CryptDevice, err::LibcryptErr};
+
+/// Keyslot context that unlocks a keyslot or a volume key with a single credential.
+///
+/// libcryptsetup does not copy the credential when the context is initialized so
+/// any borrowed data must outlive the context.
+pub struct CryptKeyslotContext<'a> {
+    ptr: *mut crypt_keyslot_context,
+    #[allow(dead_code)]
+    cstring: Option<CString>,
+    phantomdata: PhantomData<&'a ()>,
+}
+
+impl<'a> CryptKeyslotContext<'a> {
+    fn new(ptr: *mut crypt_keyslot_context, cstring: Option<CString>) -> Self {
+        CryptKeyslotContext {
+            ptr,
+            cstring,
+            phantomdata: PhantomData,
+        }
+    }
+
+    /// Initialize a keyslot context with a passphrase
+    pub fn init_by_passphrase(
+        device: &mut CryptDevice,
+        passphrase: &'a [u8],
+    ) -> Result<Self, LibcryptErr> {
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_passphrase(
+                device.as_ptr(),
+                to_byte_ptr!(passphrase),
+                passphrase.len(),
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, None))
+    }
+
+    /// Initialize a keyslot context with a key file.
+    ///
+    /// A value of `None` for the size reads the key file until EOF.
+    pub fn init_by_keyfile(
+        device: &mut CryptDevice,
+        keyfile: &Path,
+        keyfile_size: Option<crate::size_t>,
+        keyfile_offset: u64,
+    ) -> Result<Self, LibcryptErr> {
+        let keyfile_cstring = path_to_cstring!(keyfile)?;
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_keyfile(
+                device.as_ptr(),
+                keyfile_cstring.as_ptr(),
+                keyfile_size.unwrap_or(0),
+                keyfile_offset,
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, Some(keyfile_cstring)))
+    }
+
+    /// Initialize a keyslot context with a LUKS2 token.
+    ///
+    /// A value of `None` for the token will try all tokens and a value of `None`
+    /// for the type will try tokens of any type.
+    pub fn init_by_token<T>(
+        device: &mut CryptDevice,
+        token: Option<c_uint>,
+        type_: Option<&str>,
+        pin: Option<&'a [u8]>,
+        usrdata: Option<&'a mut T>,
+    ) -> Result<Self, LibcryptErr> {
+        let type_cstring = match type_ {
+            Some(t) => Some(to_cstring!(t)?),
+            None => None,
+        };
+        let (pin_ptr, pin_len) = match pin {
+            Some(p) => (to_byte_ptr!(p), p.len()),
+            None => (ptr::null(), 0),
+        };
+        let usrdata_ptr = match usrdata {
+            Some(reference) => (reference as *mut T).cast::<c_void>(),
+            None => ptr::null_mut(),
+        };
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_token(
+                device.as_ptr(),
+                token
+                    .map(|t| t as c_int)
+                    .unwrap_or(libcryptsetup_rs_sys::CRYPT_ANY_TOKEN),
+                // NOTE: Must keep as_ref to avoid use after free error.
+                type_cstring
+                    .as_ref()
+                    .map(|s| s.as_ptr())
+                    .unwrap_or_else(ptr::null),
+                pin_ptr,
+                pin_len,
+                usrdata_ptr,
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, type_cstring))
+    }
+
+    /// Initialize a keyslot context with a volume key.
+    ///
+    /// A value of `None` for the volume key will generate a new volume key when
+    /// the context is used to add a keyslot.
+    pub fn init_by_volume_key(
+        device: &mut CryptDevice,
+        volume_key: Option<&'a [u8]>,
+    ) -> Result<Self, LibcryptErr> {
+        let (volume_key_ptr, volume_key_len) = match volume_key {
+            Some(vk) => (to_byte_ptr!(vk), vk.len()),
+            None => (ptr::null(), 0),
+        };
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_volume_key(
+                device.as_ptr(),
+                volume_key_ptr,
+                volume_key_len,
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, None))
+    }
+
+    /// Initialize a keyslot context with a volume key and its signature
+    #[cfg(cryptsetup27supported)]
+    pub fn init_by_signed_key(
+        device: &mut CryptDevice,
+        volume_key: &'a [u8],
+        signature: &'a [u8],
+    ) -> Result<Self, LibcryptErr> {
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_signed_key(
+                device.as_ptr(),
+                to_byte_ptr!(volume_key),
+                volume_key.len(),
+                to_byte_ptr!(signature),
+                signature.len(),
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, None))
+    }
+
+    /// Initialize a keyslot context with a passphrase stored in the kernel keyring
+    #[cfg(cryptsetup27supported)]
+    pub fn init_by_keyring(
+        device: &mut CryptDevice,
+        key_description: &str,
+    ) -> Result<Self, LibcryptErr> {
+        let description_cstring = to_cstring!(key_description)?;
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_keyring(
+                device.as_ptr(),
+                description_cstring.as_ptr(),
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, Some(description_cstring)))
+    }
+
+    /// Initialize a keyslot context with a volume key stored in the kernel keyring
+    #[cfg(cryptsetup27supported)]
+    pub fn init_by_vk_in_keyring(
+        device: &mut CryptDevice,
+        key_description: &str,
+    ) -> Result<Self, LibcryptErr> {
+        let description_cstring = to_cstring!(key_description)?;
+        let mut kc: *mut crypt_keyslot_context = ptr::null_mut();
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_init_by_vk_in_keyring(
+                device.as_ptr(),
+                description_cstring.as_ptr(),
+                &mut kc as *mut _,
+            )
+        ))?;
+        Ok(CryptKeyslotContext::new(kc, Some(description_cstring)))
+    }
+
+    /// Get the error from the last failed operation that used this keyslot context
+    pub fn get_error(&mut self) -> Result<(), LibcryptErr> {
+        errno!(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_get_error(self.ptr)
+        ))
+    }
+
+    /// Set the PIN for a token keyslot context
+    pub fn set_pin(&mut self, device: &mut CryptDevice, pin: &'a [u8]) -> Result<(), LibcryptErr> {
+        errno!(mutex!(libcryptsetup_rs_sys::crypt_keyslot_context_set_pin(
+            device.as_ptr(),
+            to_byte_ptr!(pin),
+            pin.len(),
+            self.ptr,
+        )))
+    }
+
+    /// Get the type of credential held by this keyslot context
+    pub fn get_type(&self) -> Result<CryptKeyslotContextType, LibcryptErr> {
+        CryptKeyslotContextType::try_from(mutex!(
+            libcryptsetup_rs_sys::crypt_keyslot_context_get_type(self.ptr)
+        ))
+    }
+
+    pub(crate) fn as_ptr(&mut self) -> *mut crypt_keyslot_context {
+        self.ptr
+    }
+}
+
+impl Drop for CryptKeyslotContext<'_> {
+    fn drop(&mut self) {
+        mutex!(libcryptsetup_rs_sys::crypt_keyslot_context_free(self.ptr))
+    }
+}
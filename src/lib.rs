@@ -19,33 +19,63 @@ mod macros;
 
 mod activate;
 mod backup;
+mod callback;
 pub mod consts;
 mod context;
 mod debug;
 mod device;
 mod err;
 mod format;
+mod hash;
+mod integrity;
 mod key;
 mod keyfile;
 mod keyslot;
+#[cfg(cryptsetup26supported)]
+mod keyslot_context;
 mod log;
+mod luks1;
 mod luks2;
 mod mem;
 mod runtime;
 mod settings;
 mod status;
+mod tcrypt;
 #[cfg(test)]
 mod tests;
+mod verity;
+mod verity_tree;
 mod wipe;
 
+#[cfg(cryptsetup26supported)]
+pub use crate::keyslot_context::CryptKeyslotContext;
+#[cfg(cryptsetup27supported)]
+pub use crate::luks2::token::set_token_external_path;
+#[cfg(cryptsetup24supported)]
+pub use crate::luks2::token::{disable_token_external, get_token_external_path, CryptTokenIter};
+
+/// Items used by the expansion of `export_token_plugin!`
+#[doc(hidden)]
+pub mod __token_plugin {
+    pub use crate::luks2::token::{
+        token_handler_buffer_free, token_handler_dump, token_handler_open, token_handler_open_pin,
+        token_handler_validate,
+    };
+    pub use libc::{c_char, c_int, c_void, size_t};
+    pub use libcryptsetup_rs_sys::crypt_device;
+}
+#[cfg(cryptsetup23supported)]
+pub use crate::backup::CryptHeaderBackup;
+#[cfg(cryptsetup24supported)]
+pub use crate::luks2::reencrypt::{ReencryptCancelToken, ReencryptJob, ReencryptJobProgress};
 #[cfg(cryptsetup23supported)]
 pub use crate::mem::{SafeBorrowedMemZero, SafeMemzero, SafeOwnedMemZero};
 pub use crate::{
     activate::CryptActivationHandle,
-    backup::CryptBackupHandle,
+    backup::{CryptBackupHandle, CryptHeaderDiff},
     context::CryptContextHandle,
     debug::set_debug_level,
-    device::{CryptDevice, CryptInit},
+    device::{CryptDevice, CryptInit, CryptInitBuilder},
     err::LibcryptErr,
     format::{
         CryptFormatHandle, CryptParamsIntegrity, CryptParamsIntegrityRef, CryptParamsLoopaes,
@@ -53,19 +83,36 @@ pub use crate::{
         CryptParamsLuks2Ref, CryptParamsPlain, CryptParamsPlainRef, CryptParamsTcrypt,
         CryptParamsTcryptRef, CryptParamsVerity, CryptParamsVerityRef,
     },
+    integrity::{CryptIntegrityHandle, CryptIntegrityMode, CryptIntegrityReport},
     key::CryptVolumeKeyHandle,
     keyfile::{CryptKeyfileContents, CryptKeyfileHandle},
     keyslot::CryptKeyslotHandle,
-    log::{log, set_log_callback},
+    log::{install_log_bridge, log, set_log_callback, set_log_fn},
+    luks1::{Luks1Header, Luks1Keyslot, LUKS1_NUM_KEYSLOTS, LUKS1_PHDR_SIZE},
     luks2::{
         flags::CryptLuks2FlagsHandle,
+        header::{Luks2BinaryHeader, Luks2HeaderCopy, Luks2HeaderState, Luks2OnDiskHeader},
+        metadata::{
+            Luks2Config, Luks2Digest, Luks2Keyslot, Luks2KeyslotAf, Luks2KeyslotArea,
+            Luks2KeyslotKdf, Luks2Metadata, Luks2Segment,
+        },
         reencrypt::{CryptLuks2ReencryptHandle, CryptParamsReencrypt, CryptParamsReencryptRef},
-        token::{register, CryptLuks2TokenHandle, CryptTokenInfo, TokenInput},
+        token::{
+            register, register_handler, CryptLuks2TokenHandle, CryptTokenEntry, CryptTokenInfo,
+            TokenHandler, TokenInput,
+        },
+        token_types::{
+            ClevisToken, Luks2KeyringToken, Luks2Token, SystemdFido2Token, SystemdPkcs11Token,
+            SystemdRecoveryToken, SystemdTpm2Token, TypedLuks2Token,
+        },
     },
     mem::SafeMemHandle,
-    runtime::{ActiveDevice, CryptRuntimeHandle},
+    runtime::{active_mappings, ActiveDevice, ActiveMapping, CryptRuntimeHandle},
     settings::{CryptPbkdfType, CryptPbkdfTypeRef, CryptSettingsHandle},
     status::{get_sector_size, status, CryptDeviceStatusHandle},
+    tcrypt::{TcryptError, TcryptHeader, TcryptOpen},
+    verity::{CryptVerityHandle, CryptVerityRootHash, DEFAULT_VERITY_SALT_SIZE},
+    verity_tree::{VerityHashAlgorithm, VerityHashTree},
     wipe::CryptWipeHandle,
 };
 
@@ -93,6 +140,24 @@ mod test {
         tests::encrypt::test_encrypt_by_password();
     }
 
+    #[ignore]
+    #[test]
+    fn test_active_mappings() {
+        tests::encrypt::test_active_mappings();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_wrong_passphrase_error() {
+        tests::encrypt::test_wrong_passphrase_error();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_closure_callbacks() {
+        tests::encrypt::test_closure_callbacks();
+    }
+
     #[ignore]
     #[test]
     #[cfg(cryptsetup24supported)]
@@ -100,6 +165,13 @@ mod test {
         tests::reencrypt::test_reencrypt_by_password();
     }
 
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup24supported)]
+    fn test_reencrypt_job_cancel_resume() {
+        tests::reencrypt::test_reencrypt_job_cancel_resume();
+    }
+
     #[ignore]
     #[test]
     fn test_encrypt_by_keyfile() {
@@ -118,6 +190,139 @@ mod test {
         tests::encrypt::test_unencrypted();
     }
 
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup26supported)]
+    fn test_add_keyslot_by_keyfile_context() {
+        tests::keyslot_context::test_add_keyslot_by_keyfile_context();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_verity_activate_without_signature() {
+        tests::verity::test_verity_activate_without_signature();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_verity_format_verify_activate() {
+        tests::verity::test_verity_format_verify_activate();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_verity_hash_tree_matches_libcryptsetup() {
+        tests::verity::test_verity_hash_tree_matches_libcryptsetup();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_integrity_journal_keys() {
+        tests::integrity::test_integrity_journal_keys();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_integrity_bitmap_and_direct_modes() {
+        tests::integrity::test_integrity_bitmap_and_direct_modes();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_bitlk_load() {
+        tests::bitlk::test_bitlk_load();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_bitlk_no_header() {
+        tests::bitlk::test_bitlk_no_header();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_tcrypt_load_headers() {
+        tests::tcrypt::test_tcrypt_load_headers();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_tcrypt_load_keyfile_and_pim() {
+        tests::tcrypt::test_tcrypt_load_keyfile_and_pim();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_tcrypt_no_header() {
+        tests::tcrypt::test_tcrypt_no_header();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_tcrypt_activate() {
+        tests::tcrypt::test_tcrypt_activate();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup24supported)]
+    fn test_token_iter() {
+        tests::token::test_token_iter();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_header_backup_restore_memory() {
+        tests::backup::test_header_backup_restore_memory();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup23supported)]
+    fn test_header_restore_checked() {
+        tests::backup::test_header_restore_checked();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup24supported)]
+    fn test_init_builder_detached_header() {
+        tests::init::test_init_builder_detached_header();
+    }
+
+    #[ignore]
+    #[test]
+    fn test_luks1_header() {
+        tests::luks1::test_luks1_header();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup24supported)]
+    fn test_luks2_metadata() {
+        tests::metadata::test_luks2_metadata();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup24supported)]
+    fn test_luks2_on_disk_header() {
+        tests::metadata::test_luks2_on_disk_header();
+    }
+
+    #[ignore]
+    #[test]
+    #[cfg(cryptsetup27supported)]
+    fn test_external_token_plugin() {
+        tests::token_plugin::test_external_token_plugin();
+    }
+
     #[ignore]
     #[test]
     fn test_crypt_setup_free_exists() {
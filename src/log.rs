@@ -3,13 +3,238 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
+    cell::{Cell, RefCell},
+    ffi::CStr,
+    mem,
     os::raw::{c_char, c_int, c_void},
+    panic::{self, AssertUnwindSafe},
     ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
 };
 
+use libcryptsetup_rs_sys::crypt_device;
+
 use crate::{consts::vals::CryptLogLevel, err::LibcryptErr};
 
-type LoggingCallback = unsafe extern "C" fn(level: c_int, msg: *const c_char, usrptr: *mut c_void);
+pub(crate) type LoggingCallback =
+    unsafe extern "C" fn(level: c_int, msg: *const c_char, usrptr: *mut c_void);
+
+/// Target used for records forwarded by the log bridge
+const LOG_BRIDGE_TARGET: &str = "libcryptsetup";
+
+/// Whether the global log callback is the log bridge
+static LOG_BRIDGE_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Boxed closure receiving log messages
+pub(crate) type LogFn = Box<dyn FnMut(CryptLogLevel, &str)>;
+
+/// Closure set with `set_log_fn`
+#[allow(clippy::type_complexity)]
+static GLOBAL_LOG_FN: Mutex<Option<Box<dyn FnMut(CryptLogLevel, &str) + Send>>> = Mutex::new(None);
+
+thread_local! {
+    /// Messages logged for a device during the current libcryptsetup call
+    static CAPTURED_MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    /// Number of libcryptsetup calls in progress, greater than one when a
+    /// callback calls back into libcryptsetup
+    static CAPTURE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Guard held for the duration of every libcryptsetup call.
+///
+/// Captured messages are discarded only when the outermost call starts so
+/// that calls made from callbacks do not discard the messages of the call
+/// that invoked them.
+pub(crate) struct CaptureScope;
+
+impl CaptureScope {
+    pub(crate) fn enter() -> Self {
+        let outermost = CAPTURE_DEPTH
+            .try_with(|d| {
+                d.set(d.get() + 1);
+                d.get() == 1
+            })
+            .unwrap_or(false);
+        if outermost {
+            let _ = CAPTURED_MESSAGES.try_with(|m| {
+                if let Ok(mut m) = m.try_borrow_mut() {
+                    m.clear();
+                }
+            });
+        }
+        CaptureScope
+    }
+}
+
+impl Drop for CaptureScope {
+    fn drop(&mut self) {
+        let _ = CAPTURE_DEPTH.try_with(|d| d.set(d.get().saturating_sub(1)));
+    }
+}
+
+/// Get the messages captured during the last libcryptsetup call. They are
+/// only taken once the outermost call has returned and are copied when an
+/// error is created from within a callback.
+pub(crate) fn take_captured_messages() -> Vec<String> {
+    let nested = CAPTURE_DEPTH.try_with(|d| d.get() > 0).unwrap_or(false);
+    CAPTURED_MESSAGES
+        .try_with(|m| {
+            m.try_borrow_mut()
+                .map(|mut m| {
+                    if nested {
+                        m.clone()
+                    } else {
+                        mem::take(&mut *m)
+                    }
+                })
+                .unwrap_or_default()
+        })
+        .unwrap_or_default()
+}
+
+/// Destination of the log messages of a device
+pub(crate) enum DeviceLogTarget {
+    /// Forward to the global log callback
+    Global,
+    /// Forward to a C callback with its user data
+    Callback(LoggingCallback, *mut c_void),
+    /// Forward to the `log` crate
+    Bridge,
+    /// Forward to a closure
+    Closure(LogFn),
+}
+
+/// Log state of a device initialized through `CryptInit`, passed as user
+/// data to `capture_log_callback`
+pub(crate) struct DeviceLog {
+    pub(crate) device: *mut crypt_device,
+    pub(crate) target: DeviceLogTarget,
+}
+
+/// Log callback installed on every device initialized through `CryptInit`.
+///
+/// Non-debug messages are recorded so that they can be attached to the
+/// error returned from the failing call, then forwarded to the target of
+/// the device.
+pub(crate) unsafe extern "C" fn capture_log_callback(
+    level: c_int,
+    msg: *const c_char,
+    usrptr: *mut c_void,
+) {
+    if msg.is_null() {
+        return;
+    }
+    if level >= 0 {
+        let message = CStr::from_ptr(msg).to_string_lossy().trim_end().to_string();
+        // The callback may run while the thread-local is being dropped at
+        // thread exit, in which case the message is only forwarded.
+        let _ = CAPTURED_MESSAGES.try_with(|m| {
+            if let Ok(mut m) = m.try_borrow_mut() {
+                m.push(message);
+            }
+        });
+    }
+
+    // Called from within a libcryptsetup call, so the lock is already held.
+    match usrptr.cast::<DeviceLog>().as_mut() {
+        Some(DeviceLog {
+            target: DeviceLogTarget::Callback(callback, callback_usrptr),
+            ..
+        }) => callback(level, msg, *callback_usrptr),
+        Some(DeviceLog {
+            target: DeviceLogTarget::Closure(log_fn),
+            ..
+        }) => call_log_fn(log_fn, level, msg),
+        Some(DeviceLog {
+            device,
+            target: DeviceLogTarget::Bridge,
+        }) => log_bridge_callback(level, msg, device.cast::<c_void>()),
+        Some(DeviceLog {
+            device,
+            target: DeviceLogTarget::Global,
+        }) if LOG_BRIDGE_INSTALLED.load(Ordering::Relaxed) => {
+            log_bridge_callback(level, msg, device.cast::<c_void>())
+        }
+        _ => libcryptsetup_rs_sys::crypt_log(ptr::null_mut(), level, msg),
+    }
+}
+
+/// Call a log closure, discarding panics so that they do not unwind into
+/// libcryptsetup
+unsafe fn call_log_fn<F>(log_fn: &mut F, level: c_int, msg: *const c_char)
+where
+    F: FnMut(CryptLogLevel, &str) + ?Sized,
+{
+    if let Ok(level) = CryptLogLevel::try_from(level) {
+        let msg = CStr::from_ptr(msg).to_string_lossy();
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| log_fn(level, msg.trim_end())));
+    }
+}
+
+/// Global log callback calling the closure set with `set_log_fn`
+unsafe extern "C" fn log_fn_trampoline(level: c_int, msg: *const c_char, _usrptr: *mut c_void) {
+    if msg.is_null() {
+        return;
+    }
+    // A closure that logs through libcryptsetup itself must not deadlock.
+    if let Ok(mut guard) = GLOBAL_LOG_FN.try_lock() {
+        if let Some(ref mut log_fn) = *guard {
+            call_log_fn(log_fn.as_mut(), level, msg);
+        }
+    }
+}
+
+fn log_bridge_level(level: c_int) -> ::log::Level {
+    match CryptLogLevel::try_from(level) {
+        Ok(CryptLogLevel::Error) => ::log::Level::Error,
+        Ok(CryptLogLevel::Normal) => ::log::Level::Info,
+        Ok(CryptLogLevel::Verbose) | Ok(CryptLogLevel::Debug) => ::log::Level::Debug,
+        Ok(CryptLogLevel::DebugJson) | Err(_) => ::log::Level::Trace,
+    }
+}
+
+/// Log callback forwarding to the `log` crate. `usrptr` is the device the
+/// message was logged for or null.
+pub(crate) unsafe extern "C" fn log_bridge_callback(
+    level: c_int,
+    msg: *const c_char,
+    usrptr: *mut c_void,
+) {
+    if msg.is_null() {
+        return;
+    }
+    let level = log_bridge_level(level);
+    let msg = CStr::from_ptr(msg).to_string_lossy();
+    let msg = msg.trim_end();
+
+    let device = usrptr.cast::<crypt_device>();
+    let (path, uuid) = if device.is_null() {
+        (None, None)
+    } else {
+        let path = libcryptsetup_rs_sys::crypt_get_device_name(device);
+        let uuid = libcryptsetup_rs_sys::crypt_get_uuid(device);
+        (
+            ptr_to_option!(path).map(|p| CStr::from_ptr(p).to_string_lossy()),
+            ptr_to_option!(uuid).map(|u| CStr::from_ptr(u).to_string_lossy()),
+        )
+    };
+    match (path, uuid) {
+        (Some(path), Some(uuid)) => ::log::log!(
+            target: LOG_BRIDGE_TARGET,
+            level,
+            device = &*path,
+            uuid = &*uuid;
+            "{msg}"
+        ),
+        (Some(path), None) => {
+            ::log::log!(target: LOG_BRIDGE_TARGET, level, device = &*path; "{msg}")
+        }
+        _ => ::log::log!(target: LOG_BRIDGE_TARGET, level, "{msg}"),
+    }
+}
 
 /// Generate a log entry
 pub fn log(level: CryptLogLevel, msg: &str) -> Result<(), LibcryptErr> {
@@ -22,8 +247,13 @@ pub fn log(level: CryptLogLevel, msg: &str) -> Result<(), LibcryptErr> {
     Ok(())
 }
 
-/// Set the callback to be executed on logging events
+/// Set the callback to be executed on logging events.
+///
+/// Messages logged for devices initialized through `CryptInit` are also
+/// attached to the error returned from the failing call.
 pub fn set_log_callback<T>(callback: Option<LoggingCallback>, usrdata: Option<&mut T>) {
+    LOG_BRIDGE_INSTALLED.store(false, Ordering::Relaxed);
+    clear_log_fn();
     mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
         ptr::null_mut(),
         callback,
@@ -33,3 +263,215 @@ pub fn set_log_callback<T>(callback: Option<LoggingCallback>, usrdata: Option<&m
         },
     ))
 }
+
+/// Forward all libcryptsetup log messages to the `log` crate, replacing any
+/// callback set with `set_log_callback`.
+///
+/// Records use the `libcryptsetup` target. `CryptLogLevel::Error` maps to
+/// `Level::Error`, `Normal` to `Info`, `Verbose` and `Debug` to `Debug` and
+/// `DebugJson` to `Trace`. Messages logged for a device carry its path in
+/// the `device` field and, once a header is loaded, its UUID in the `uuid`
+/// field.
+pub fn install_log_bridge() {
+    clear_log_fn();
+    mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
+        ptr::null_mut(),
+        Some(log_bridge_callback),
+        ptr::null_mut(),
+    ));
+    LOG_BRIDGE_INSTALLED.store(true, Ordering::Relaxed);
+}
+
+fn clear_log_fn() {
+    if let Ok(mut guard) = GLOBAL_LOG_FN.lock() {
+        *guard = None;
+    }
+}
+
+/// Set a closure to be executed on logging events, replacing any callback
+/// set with `set_log_callback`.
+///
+/// Panics in the closure are caught and discarded.
+pub fn set_log_fn<F>(log_fn: F)
+where
+    F: FnMut(CryptLogLevel, &str) + Send + 'static,
+{
+    LOG_BRIDGE_INSTALLED.store(false, Ordering::Relaxed);
+    if let Ok(mut guard) = GLOBAL_LOG_FN.lock() {
+        *guard = Some(Box::new(log_fn));
+    }
+    mutex!(libcryptsetup_rs_sys::crypt_set_log_callback(
+        ptr::null_mut(),
+        Some(log_fn_trampoline),
+        ptr::null_mut(),
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use ::log::{kv::Key, Level, Log, Metadata, Record};
+
+    struct TestLogger(Mutex<Vec<(Level, String, String, bool)>>);
+
+    impl Log for TestLogger {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push((
+                record.level(),
+                record.target().to_string(),
+                record.args().to_string(),
+                record.key_values().get(Key::from("device")).is_some(),
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+
+    unsafe extern "C" fn counting_callback(_: c_int, _: *const c_char, usrptr: *mut c_void) {
+        *usrptr.cast::<u32>() += 1;
+    }
+
+    #[test]
+    fn test_log_bridge_callback() {
+        // The logger is process-global and can only be set once.
+        if ::log::set_logger(&LOGGER).is_err() {
+            assert!(
+                ptr::addr_eq(::log::logger(), &LOGGER),
+                "another logger is installed"
+            );
+        }
+        ::log::set_max_level(::log::LevelFilter::Trace);
+
+        unsafe {
+            log_bridge_callback(
+                libcryptsetup_rs_sys::CRYPT_LOG_ERROR as c_int,
+                c"test_log_bridge_callback: Device is busy.\n".as_ptr(),
+                ptr::null_mut(),
+            );
+            log_bridge_callback(
+                libcryptsetup_rs_sys::CRYPT_LOG_DEBUG_JSON,
+                c"test_log_bridge_callback: {}".as_ptr(),
+                ptr::null_mut(),
+            );
+        }
+        // Other tests may log concurrently.
+        let records = LOGGER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, target, msg, _)| {
+                target == LOG_BRIDGE_TARGET && msg.starts_with("test_log_bridge_callback: ")
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                (
+                    Level::Error,
+                    LOG_BRIDGE_TARGET.to_string(),
+                    "test_log_bridge_callback: Device is busy.".to_string(),
+                    false
+                ),
+                (
+                    Level::Trace,
+                    LOG_BRIDGE_TARGET.to_string(),
+                    "test_log_bridge_callback: {}".to_string(),
+                    false
+                ),
+            ]
+        );
+        assert_eq!(
+            log_bridge_level(libcryptsetup_rs_sys::CRYPT_LOG_NORMAL as c_int),
+            Level::Info
+        );
+        assert_eq!(
+            log_bridge_level(libcryptsetup_rs_sys::CRYPT_LOG_VERBOSE as c_int),
+            Level::Debug
+        );
+    }
+
+    #[test]
+    fn test_capture_log_callback() {
+        let mut count = 0u32;
+        let mut device_log = DeviceLog {
+            device: ptr::null_mut(),
+            target: DeviceLogTarget::Callback(
+                counting_callback,
+                (&mut count as *mut u32).cast::<c_void>(),
+            ),
+        };
+        drop(CaptureScope::enter());
+        unsafe {
+            capture_log_callback(
+                libcryptsetup_rs_sys::CRYPT_LOG_ERROR as c_int,
+                c"No key available with this passphrase.\n".as_ptr(),
+                (&mut device_log as *mut DeviceLog).cast::<c_void>(),
+            );
+            capture_log_callback(
+                libcryptsetup_rs_sys::CRYPT_LOG_DEBUG,
+                c"Trying to open keyslot 0.".as_ptr(),
+                (&mut device_log as *mut DeviceLog).cast::<c_void>(),
+            );
+        }
+        assert_eq!(count, 2);
+        assert_eq!(
+            take_captured_messages(),
+            vec!["No key available with this passphrase.".to_string()]
+        );
+        assert!(take_captured_messages().is_empty());
+    }
+
+    #[test]
+    fn test_nested_capture() {
+        let mut device_log = DeviceLog {
+            device: ptr::null_mut(),
+            target: DeviceLogTarget::Closure(Box::new(|_, _| ())),
+        };
+        let usrptr = (&mut device_log as *mut DeviceLog).cast::<c_void>();
+
+        let outer = CaptureScope::enter();
+        unsafe {
+            capture_log_callback(
+                libcryptsetup_rs_sys::CRYPT_LOG_ERROR as c_int,
+                c"No key available with this passphrase.".as_ptr(),
+                usrptr,
+            );
+        }
+        {
+            // A call made from a callback while the outer call is running,
+            // failing in turn
+            let _inner = CaptureScope::enter();
+            unsafe {
+                capture_log_callback(
+                    libcryptsetup_rs_sys::CRYPT_LOG_ERROR as c_int,
+                    c"Token 1 not found.".as_ptr(),
+                    usrptr,
+                );
+            }
+        }
+        let inner_err = LibcryptErr::from_errno(libc::ENOENT);
+        assert_eq!(inner_err.messages().len(), 2);
+        drop(outer);
+
+        let outer_err = LibcryptErr::from_errno(libc::EPERM);
+        assert_eq!(
+            outer_err.messages(),
+            [
+                "No key available with this passphrase.".to_string(),
+                "Token 1 not found.".to_string()
+            ]
+        );
+        assert!(take_captured_messages().is_empty());
+    }
+}
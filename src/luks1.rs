@@ -0,0 +1,272 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fs::File, io::Read, path::Path};
+
+use libc::c_uint;
+
+use crate::{consts::vals::KeyslotInfo, err::LibcryptErr, format::CryptParamsLuks1};
+
+const LUKS1_MAGIC: &[u8; 6] = b"LUKS\xba\xbe";
+/// Size in bytes of the LUKS1 partition header including the keyslot table
+pub const LUKS1_PHDR_SIZE: usize = 592;
+/// Number of keyslots in a LUKS1 header
+pub const LUKS1_NUM_KEYSLOTS: usize = 8;
+const LUKS1_KEYSLOT_TABLE_OFFSET: usize = 208;
+const LUKS1_KEYSLOT_SIZE: usize = 48;
+const LUKS1_KEY_ENABLED: u32 = 0x00ac_71f3;
+const LUKS1_KEY_DISABLED: u32 = 0x0000_dead;
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    let mut buf = [0u8; 2];
+    buf.copy_from_slice(&bytes[..2]);
+    u16::from_be_bytes(buf)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_be_bytes(buf)
+}
+
+fn c_string(bytes: &[u8]) -> Result<String, LibcryptErr> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end])
+        .map(|s| s.to_string())
+        .map_err(LibcryptErr::Utf8Error)
+}
+
+fn invalid(msg: String) -> LibcryptErr {
+    LibcryptErr::Other(msg)
+}
+
+/// A keyslot entry of a LUKS1 header
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks1Keyslot {
+    /// Whether the keyslot is enabled
+    pub active: bool,
+    /// PBKDF2 iterations for the passphrase
+    pub iterations: u32,
+    /// PBKDF2 salt for the passphrase
+    pub salt: [u8; 32],
+    /// Offset of the key material in 512 byte sectors
+    pub key_material_offset: u32,
+    /// Number of anti-forensic stripes
+    pub stripes: u32,
+}
+
+impl Luks1Keyslot {
+    fn parse(bytes: &[u8]) -> Result<Self, LibcryptErr> {
+        let active = match be_u32(&bytes[0..4]) {
+            LUKS1_KEY_ENABLED => true,
+            LUKS1_KEY_DISABLED => false,
+            state => return Err(invalid(format!("Invalid LUKS1 keyslot state {state:#x}"))),
+        };
+        let mut salt = [0u8; 32];
+        salt.copy_from_slice(&bytes[8..40]);
+        Ok(Luks1Keyslot {
+            active,
+            iterations: be_u32(&bytes[4..8]),
+            salt,
+            key_material_offset: be_u32(&bytes[40..44]),
+            stripes: be_u32(&bytes[44..48]),
+        })
+    }
+}
+
+/// A LUKS1 partition header read without libcryptsetup
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks1Header {
+    /// Format version, 1 for LUKS1
+    pub version: u16,
+    /// Cipher name, for example `aes`
+    pub cipher: String,
+    /// Cipher mode, for example `xts-plain64`
+    pub cipher_mode: String,
+    /// Hash specification used for PBKDF2 and the anti-forensic splitter
+    pub hash: String,
+    /// Offset of the encrypted data in 512 byte sectors
+    pub payload_offset: u32,
+    /// Size of the volume key in bytes
+    pub key_bytes: u32,
+    /// PBKDF2 digest of the volume key
+    pub mk_digest: [u8; 20],
+    /// Salt of the volume key digest
+    pub mk_digest_salt: [u8; 32],
+    /// PBKDF2 iterations of the volume key digest
+    pub mk_digest_iterations: u32,
+    /// UUID of the device
+    pub uuid: String,
+    /// Keyslot table
+    pub keyslots: [Luks1Keyslot; LUKS1_NUM_KEYSLOTS],
+}
+
+impl Luks1Header {
+    /// Parse a LUKS1 header from the start of a byte slice
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LibcryptErr> {
+        let bytes = bytes
+            .get(..LUKS1_PHDR_SIZE)
+            .ok_or_else(|| invalid("LUKS1 header is truncated".to_string()))?;
+        if &bytes[..LUKS1_MAGIC.len()] != LUKS1_MAGIC {
+            return Err(invalid("No LUKS1 header found".to_string()));
+        }
+        let version = be_u16(&bytes[6..8]);
+        if version != 1 {
+            return Err(invalid(format!("Unsupported LUKS version {version}")));
+        }
+
+        let mut keyslots = Vec::with_capacity(LUKS1_NUM_KEYSLOTS);
+        for chunk in bytes[LUKS1_KEYSLOT_TABLE_OFFSET..].chunks_exact(LUKS1_KEYSLOT_SIZE) {
+            keyslots.push(Luks1Keyslot::parse(chunk)?);
+        }
+        let mut mk_digest = [0u8; 20];
+        mk_digest.copy_from_slice(&bytes[112..132]);
+        let mut mk_digest_salt = [0u8; 32];
+        mk_digest_salt.copy_from_slice(&bytes[132..164]);
+
+        Ok(Luks1Header {
+            version,
+            cipher: c_string(&bytes[8..40])?,
+            cipher_mode: c_string(&bytes[40..72])?,
+            hash: c_string(&bytes[72..104])?,
+            payload_offset: be_u32(&bytes[104..108]),
+            key_bytes: be_u32(&bytes[108..112]),
+            mk_digest,
+            mk_digest_salt,
+            mk_digest_iterations: be_u32(&bytes[164..168]),
+            uuid: c_string(&bytes[168..208])?,
+            keyslots: keyslots
+                .try_into()
+                .map_err(|_| invalid("LUKS1 keyslot table is truncated".to_string()))?,
+        })
+    }
+
+    /// Read a LUKS1 header from a device or a header backup file
+    pub fn from_file(path: &Path) -> Result<Self, LibcryptErr> {
+        let mut bytes = [0u8; LUKS1_PHDR_SIZE];
+        File::open(path)
+            .and_then(|mut f| f.read_exact(&mut bytes))
+            .map_err(LibcryptErr::IOError)?;
+        Luks1Header::from_bytes(&bytes)
+    }
+
+    /// Get the format parameters this header was created with.
+    ///
+    /// `data_alignment` is set to the payload offset, which reproduces the
+    /// data layout when passed back to `CryptFormatHandle::format`.
+    pub fn params(&self) -> CryptParamsLuks1 {
+        CryptParamsLuks1 {
+            hash: self.hash.clone(),
+            data_alignment: self.payload_offset as usize,
+            data_device: None,
+        }
+    }
+
+    /// Get the status of a keyslot as `CryptKeyslotHandle::status` would
+    /// report it
+    pub fn keyslot_status(&self, keyslot: c_uint) -> KeyslotInfo {
+        match self.keyslots.get(keyslot as usize) {
+            Some(slot) if slot.active => {
+                if self.keyslots.iter().filter(|s| s.active).count() == 1 {
+                    KeyslotInfo::ActiveLast
+                } else {
+                    KeyslotInfo::Active
+                }
+            }
+            Some(_) => KeyslotInfo::Inactive,
+            None => KeyslotInfo::Invalid,
+        }
+    }
+
+    /// Iterate over the indices of all active keyslots
+    pub fn active_keyslots(&self) -> impl Iterator<Item = c_uint> + '_ {
+        self.keyslots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.active)
+            .map(|(i, _)| i as c_uint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(active: &[usize]) -> Vec<u8> {
+        let mut bytes = vec![0u8; LUKS1_PHDR_SIZE];
+        bytes[..6].copy_from_slice(LUKS1_MAGIC);
+        bytes[6..8].copy_from_slice(&1u16.to_be_bytes());
+        bytes[8..11].copy_from_slice(b"aes");
+        bytes[40..51].copy_from_slice(b"xts-plain64");
+        bytes[72..78].copy_from_slice(b"sha256");
+        bytes[104..108].copy_from_slice(&4096u32.to_be_bytes());
+        bytes[108..112].copy_from_slice(&64u32.to_be_bytes());
+        bytes[112..132].fill(0x11);
+        bytes[132..164].fill(0x22);
+        bytes[164..168].copy_from_slice(&1000u32.to_be_bytes());
+        bytes[168..204].copy_from_slice(b"4b9d7b4e-0e4f-4c2b-9d6e-8b3c1c1f2a90");
+        for i in 0..LUKS1_NUM_KEYSLOTS {
+            let slot = &mut bytes[LUKS1_KEYSLOT_TABLE_OFFSET + i * LUKS1_KEYSLOT_SIZE..]
+                [..LUKS1_KEYSLOT_SIZE];
+            let state = if active.contains(&i) {
+                LUKS1_KEY_ENABLED
+            } else {
+                LUKS1_KEY_DISABLED
+            };
+            slot[0..4].copy_from_slice(&state.to_be_bytes());
+            slot[4..8].copy_from_slice(&2000u32.to_be_bytes());
+            slot[8..40].fill(i as u8);
+            slot[40..44].copy_from_slice(&(8 + i as u32 * 512).to_be_bytes());
+            slot[44..48].copy_from_slice(&4000u32.to_be_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_luks1_header_parse() {
+        let hdr = Luks1Header::from_bytes(&header(&[0, 3])).unwrap();
+        assert_eq!(hdr.version, 1);
+        assert_eq!(hdr.cipher, "aes");
+        assert_eq!(hdr.cipher_mode, "xts-plain64");
+        assert_eq!(hdr.hash, "sha256");
+        assert_eq!(hdr.payload_offset, 4096);
+        assert_eq!(hdr.key_bytes, 64);
+        assert_eq!(hdr.mk_digest, [0x11; 20]);
+        assert_eq!(hdr.mk_digest_salt, [0x22; 32]);
+        assert_eq!(hdr.mk_digest_iterations, 1000);
+        assert_eq!(hdr.uuid, "4b9d7b4e-0e4f-4c2b-9d6e-8b3c1c1f2a90");
+        assert_eq!(hdr.keyslots[3].salt, [3; 32]);
+        assert_eq!(hdr.keyslots[3].key_material_offset, 8 + 3 * 512);
+        assert_eq!(hdr.keyslots[3].stripes, 4000);
+        assert_eq!(hdr.active_keyslots().collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(hdr.keyslot_status(0), KeyslotInfo::Active);
+        assert_eq!(hdr.keyslot_status(1), KeyslotInfo::Inactive);
+        assert_eq!(hdr.keyslot_status(8), KeyslotInfo::Invalid);
+
+        let params = hdr.params();
+        assert_eq!(params.hash, "sha256");
+        assert_eq!(params.data_alignment, 4096);
+
+        let hdr = Luks1Header::from_bytes(&header(&[5])).unwrap();
+        assert_eq!(hdr.keyslot_status(5), KeyslotInfo::ActiveLast);
+    }
+
+    #[test]
+    fn test_luks1_header_invalid() {
+        let bytes = header(&[0]);
+        assert!(Luks1Header::from_bytes(&bytes[..LUKS1_PHDR_SIZE - 1]).is_err());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = 0;
+        assert!(Luks1Header::from_bytes(&bad_magic).is_err());
+
+        let mut luks2 = bytes.clone();
+        luks2[6..8].copy_from_slice(&2u16.to_be_bytes());
+        assert!(Luks1Header::from_bytes(&luks2).is_err());
+
+        let mut bad_slot = bytes;
+        bad_slot[LUKS1_KEYSLOT_TABLE_OFFSET] = 0xff;
+        assert!(Luks1Header::from_bytes(&bad_slot).is_err());
+    }
+}
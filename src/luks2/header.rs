@@ -0,0 +1,381 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fs::File, io::Read, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    err::LibcryptErr, hash::HashAlgorithm, luks2::json::invalid, luks2::metadata::Luks2Metadata,
+};
+
+const LUKS2_MAGIC_PRIMARY: &[u8; 6] = b"LUKS\xba\xbe";
+const LUKS2_MAGIC_SECONDARY: &[u8; 6] = b"SKUL\xba\xbe";
+const LUKS2_BINARY_HEADER_SIZE: usize = 4096;
+const LUKS2_CHECKSUM_RANGE: std::ops::Range<usize> = 448..512;
+/// Offsets at which libcryptsetup looks for the secondary header if the
+/// primary header is unusable
+const LUKS2_SECONDARY_OFFSETS: [usize; 9] = [
+    0x4000, 0x8000, 0x10000, 0x20000, 0x40000, 0x80000, 0x100000, 0x200000, 0x400000,
+];
+/// Largest possible offset of the secondary header plus its largest size
+const LUKS2_MAX_HEADERS_SIZE: u64 = 2 * 0x400000;
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    let mut buf = [0u8; 2];
+    buf.copy_from_slice(&bytes[..2]);
+    u16::from_be_bytes(buf)
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Fields of the binary part of a LUKS2 header
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2BinaryHeader {
+    /// Format version, 2 for LUKS2
+    pub version: u16,
+    /// Size in bytes of the binary header and the JSON area
+    pub hdr_size: u64,
+    /// Sequence id, incremented on every metadata update
+    pub seqid: u64,
+    /// Label of the device
+    pub label: String,
+    /// Name of the checksum algorithm
+    pub checksum_alg: String,
+    /// Salt of the header
+    pub salt: Vec<u8>,
+    /// UUID of the device
+    pub uuid: String,
+    /// Subsystem label of the device
+    pub subsystem: String,
+    /// Offset in bytes of this header copy from the start of the device
+    pub hdr_offset: u64,
+    /// Checksum stored in the header, padded with zeroes to 64 bytes
+    pub checksum: Vec<u8>,
+}
+
+impl Luks2BinaryHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        Luks2BinaryHeader {
+            version: be_u16(&bytes[6..8]),
+            hdr_size: be_u64(&bytes[8..16]),
+            seqid: be_u64(&bytes[16..24]),
+            label: c_string(&bytes[24..72]),
+            checksum_alg: c_string(&bytes[72..104]),
+            salt: bytes[104..168].to_vec(),
+            uuid: c_string(&bytes[168..208]),
+            subsystem: c_string(&bytes[208..256]),
+            hdr_offset: be_u64(&bytes[256..264]),
+            checksum: bytes[LUKS2_CHECKSUM_RANGE].to_vec(),
+        }
+    }
+}
+
+/// One copy of a LUKS2 header read from disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct Luks2HeaderCopy {
+    /// Offset in bytes of the copy from the start of the device
+    pub offset: u64,
+    /// Binary header of the copy
+    pub header: Luks2BinaryHeader,
+    /// Whether the stored checksum matches the header and the JSON area
+    pub checksum_valid: bool,
+    /// Contents of the JSON area or `None` if it could not be read or parsed
+    pub json: Option<Value>,
+}
+
+impl Luks2HeaderCopy {
+    fn parse(data: &[u8], offset: usize, magic: &[u8; 6]) -> Option<Self> {
+        let bytes = data.get(offset..offset + LUKS2_BINARY_HEADER_SIZE)?;
+        if &bytes[..magic.len()] != magic {
+            return None;
+        }
+        let header = Luks2BinaryHeader::parse(bytes);
+        let area = usize::try_from(header.hdr_size)
+            .ok()
+            .filter(|size| {
+                size.is_power_of_two()
+                    && (LUKS2_SECONDARY_OFFSETS[0]..=LUKS2_SECONDARY_OFFSETS[8]).contains(size)
+            })
+            .and_then(|size| data.get(offset..offset + size));
+        let (checksum_valid, json) = match area {
+            Some(area) => {
+                let checksum_valid = HashAlgorithm::from_name(&header.checksum_alg)
+                    .map(|alg| {
+                        let mut hasher = alg.hasher();
+                        hasher.update(&area[..LUKS2_CHECKSUM_RANGE.start]);
+                        hasher.update(&[0u8; 64]);
+                        hasher.update(&area[LUKS2_CHECKSUM_RANGE.end..]);
+                        let checksum = hasher.finalize();
+                        header.checksum[..checksum.len()] == *checksum
+                            && header.checksum[checksum.len()..].iter().all(|b| *b == 0)
+                    })
+                    .unwrap_or(false);
+                let json_area = &area[LUKS2_BINARY_HEADER_SIZE..];
+                let end = json_area
+                    .iter()
+                    .position(|b| *b == 0)
+                    .unwrap_or(json_area.len());
+                (
+                    checksum_valid,
+                    serde_json::from_slice(&json_area[..end]).ok(),
+                )
+            }
+            None => (false, None),
+        };
+        Some(Luks2HeaderCopy {
+            offset: offset as u64,
+            header,
+            checksum_valid,
+            json,
+        })
+    }
+
+    /// Check whether the copy is intact and can be used
+    pub fn is_valid(&self) -> bool {
+        self.checksum_valid
+            && self.json.is_some()
+            && self.header.version == 2
+            && self.header.hdr_offset == self.offset
+    }
+
+    /// Parse the JSON area into typed metadata
+    pub fn metadata(&self) -> Result<Luks2Metadata, LibcryptErr> {
+        match self.json {
+            Some(ref json) => Luks2Metadata::try_from(json),
+            None => Err(invalid(format!(
+                "JSON area of the LUKS2 header at offset {} is unreadable",
+                self.offset
+            ))),
+        }
+    }
+}
+
+/// Comparison of the two copies of a LUKS2 header
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Luks2HeaderState {
+    /// Both copies are valid and have the same sequence id
+    InSync,
+    /// Both copies are valid and the primary copy has a higher sequence id
+    PrimaryNewer,
+    /// Both copies are valid and the secondary copy has a higher sequence id
+    SecondaryNewer,
+    /// The primary copy is missing or corrupt
+    PrimaryCorrupt,
+    /// The secondary copy is missing or corrupt
+    SecondaryCorrupt,
+    /// Both copies are missing or corrupt
+    BothCorrupt,
+}
+
+/// Both copies of a LUKS2 header read without libcryptsetup
+#[derive(Clone, Debug, PartialEq)]
+pub struct Luks2OnDiskHeader {
+    /// Primary header copy at offset 0
+    pub primary: Option<Luks2HeaderCopy>,
+    /// Secondary header copy
+    pub secondary: Option<Luks2HeaderCopy>,
+}
+
+impl Luks2OnDiskHeader {
+    /// Read the header copies from the start of a byte slice.
+    ///
+    /// Returns an error if no LUKS2 header magic is found.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, LibcryptErr> {
+        let primary = Luks2HeaderCopy::parse(data, 0, LUKS2_MAGIC_PRIMARY);
+        let secondary = primary
+            .as_ref()
+            .filter(|p| p.is_valid())
+            .and_then(|p| usize::try_from(p.header.hdr_size).ok())
+            .and_then(|offset| Luks2HeaderCopy::parse(data, offset, LUKS2_MAGIC_SECONDARY))
+            .or_else(|| {
+                LUKS2_SECONDARY_OFFSETS
+                    .iter()
+                    .find_map(|offset| Luks2HeaderCopy::parse(data, *offset, LUKS2_MAGIC_SECONDARY))
+            });
+        if primary.is_none() && secondary.is_none() {
+            return Err(invalid("No LUKS2 header found".to_string()));
+        }
+        Ok(Luks2OnDiskHeader { primary, secondary })
+    }
+
+    /// Read the header copies from a device or a header backup file
+    pub fn from_file(path: &Path) -> Result<Self, LibcryptErr> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|f| f.take(LUKS2_MAX_HEADERS_SIZE).read_to_end(&mut data))
+            .map_err(LibcryptErr::IOError)?;
+        Luks2OnDiskHeader::from_bytes(&data)
+    }
+
+    fn valid_primary(&self) -> Option<&Luks2HeaderCopy> {
+        self.primary.as_ref().filter(|c| c.is_valid())
+    }
+
+    fn valid_secondary(&self) -> Option<&Luks2HeaderCopy> {
+        self.secondary.as_ref().filter(|c| c.is_valid())
+    }
+
+    /// Compare the two header copies
+    pub fn state(&self) -> Luks2HeaderState {
+        match (self.valid_primary(), self.valid_secondary()) {
+            (Some(p), Some(s)) if p.header.seqid > s.header.seqid => Luks2HeaderState::PrimaryNewer,
+            (Some(p), Some(s)) if p.header.seqid < s.header.seqid => {
+                Luks2HeaderState::SecondaryNewer
+            }
+            (Some(_), Some(_)) => Luks2HeaderState::InSync,
+            (Some(_), None) => Luks2HeaderState::SecondaryCorrupt,
+            (None, Some(_)) => Luks2HeaderState::PrimaryCorrupt,
+            (None, None) => Luks2HeaderState::BothCorrupt,
+        }
+    }
+
+    /// Get the valid copy with the highest sequence id, preferring the primary
+    /// copy, as libcryptsetup does when it loads the header
+    pub fn newest(&self) -> Option<&Luks2HeaderCopy> {
+        match (self.valid_primary(), self.valid_secondary()) {
+            (Some(p), Some(s)) if s.header.seqid > p.header.seqid => Some(s),
+            (Some(p), _) => Some(p),
+            (None, s) => s,
+        }
+    }
+
+    /// Parse the metadata of the newest valid copy
+    pub fn metadata(&self) -> Result<Luks2Metadata, LibcryptErr> {
+        self.newest()
+            .ok_or_else(|| invalid("Both LUKS2 header copies are corrupt".to_string()))?
+            .metadata()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    const HDR_SIZE: usize = 0x4000;
+
+    fn write_copy(data: &mut [u8], offset: usize, magic: &[u8; 6], seqid: u64, json: &Value) {
+        let area = &mut data[offset..offset + HDR_SIZE];
+        area.fill(0);
+        area[..6].copy_from_slice(magic);
+        area[6..8].copy_from_slice(&2u16.to_be_bytes());
+        area[8..16].copy_from_slice(&(HDR_SIZE as u64).to_be_bytes());
+        area[16..24].copy_from_slice(&seqid.to_be_bytes());
+        area[72..78].copy_from_slice(b"sha256");
+        area[104..168].fill(0xaa);
+        area[168..204].copy_from_slice(b"d9c4f3c2-5b8e-4c57-9a55-2f1f4d0f8a3e");
+        area[256..264].copy_from_slice(&(offset as u64).to_be_bytes());
+        let json = serde_json::to_vec(json).unwrap();
+        area[LUKS2_BINARY_HEADER_SIZE..LUKS2_BINARY_HEADER_SIZE + json.len()]
+            .copy_from_slice(&json);
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(area);
+        let checksum = hasher.finalize();
+        area[448..448 + checksum.len()].copy_from_slice(&checksum);
+    }
+
+    fn metadata_json() -> Value {
+        json!({
+            "keyslots": {},
+            "tokens": {},
+            "segments": {
+                "0": {
+                    "type": "crypt",
+                    "offset": "16777216",
+                    "size": "dynamic",
+                    "iv_tweak": "0",
+                    "encryption": "aes-xts-plain64",
+                    "sector_size": 512
+                }
+            },
+            "digests": {},
+            "config": {"json_size": "12288", "keyslots_size": "16744448"}
+        })
+    }
+
+    fn image(primary_seqid: u64, secondary_seqid: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 2 * HDR_SIZE];
+        write_copy(
+            &mut data,
+            0,
+            LUKS2_MAGIC_PRIMARY,
+            primary_seqid,
+            &metadata_json(),
+        );
+        write_copy(
+            &mut data,
+            HDR_SIZE,
+            LUKS2_MAGIC_SECONDARY,
+            secondary_seqid,
+            &metadata_json(),
+        );
+        data
+    }
+
+    #[test]
+    fn test_header_in_sync() {
+        let header = Luks2OnDiskHeader::from_bytes(&image(3, 3)).unwrap();
+        assert_eq!(header.state(), Luks2HeaderState::InSync);
+        let primary = header.primary.as_ref().unwrap();
+        assert_eq!(primary.header.checksum_alg, "sha256");
+        assert_eq!(primary.header.uuid, "d9c4f3c2-5b8e-4c57-9a55-2f1f4d0f8a3e");
+        assert_eq!(header.secondary.as_ref().unwrap().offset, HDR_SIZE as u64);
+        assert_eq!(header.newest().unwrap().offset, 0);
+        assert_eq!(
+            header.metadata().unwrap(),
+            Luks2Metadata::try_from(&metadata_json()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_header_newer_copy() {
+        let header = Luks2OnDiskHeader::from_bytes(&image(3, 4)).unwrap();
+        assert_eq!(header.state(), Luks2HeaderState::SecondaryNewer);
+        assert_eq!(header.newest().unwrap().offset, HDR_SIZE as u64);
+
+        let header = Luks2OnDiskHeader::from_bytes(&image(5, 4)).unwrap();
+        assert_eq!(header.state(), Luks2HeaderState::PrimaryNewer);
+        assert_eq!(header.newest().unwrap().offset, 0);
+    }
+
+    #[test]
+    fn test_header_corrupt_copy() {
+        let mut data = image(3, 3);
+        data[LUKS2_BINARY_HEADER_SIZE + 1] ^= 0xff;
+        let header = Luks2OnDiskHeader::from_bytes(&data).unwrap();
+        assert!(!header.primary.as_ref().unwrap().checksum_valid);
+        assert_eq!(header.state(), Luks2HeaderState::PrimaryCorrupt);
+        assert_eq!(header.newest().unwrap().offset, HDR_SIZE as u64);
+        assert!(header.metadata().is_ok());
+
+        // Corrupt magic of the primary copy; the secondary copy is found by scanning.
+        data[0] = 0;
+        let header = Luks2OnDiskHeader::from_bytes(&data).unwrap();
+        assert!(header.primary.is_none());
+        assert_eq!(header.state(), Luks2HeaderState::PrimaryCorrupt);
+
+        let mut data = image(3, 3);
+        data[HDR_SIZE + 16] ^= 0xff;
+        let header = Luks2OnDiskHeader::from_bytes(&data).unwrap();
+        assert_eq!(header.state(), Luks2HeaderState::SecondaryCorrupt);
+
+        data[LUKS2_BINARY_HEADER_SIZE + 1] ^= 0xff;
+        let header = Luks2OnDiskHeader::from_bytes(&data).unwrap();
+        assert_eq!(header.state(), Luks2HeaderState::BothCorrupt);
+        assert!(header.metadata().is_err());
+
+        assert!(Luks2OnDiskHeader::from_bytes(&[0u8; 2 * HDR_SIZE]).is_err());
+    }
+}
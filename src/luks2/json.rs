@@ -0,0 +1,188 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Helpers to parse the fields of LUKS2 JSON metadata objects. Each helper
+//! removes the field from the map so that the remaining fields can be kept as
+//! extensions.
+
+use libc::c_uint;
+use serde_json::{Map, Value};
+
+use crate::err::LibcryptErr;
+
+pub(crate) fn invalid(msg: String) -> LibcryptErr {
+    LibcryptErr::Other(msg)
+}
+
+fn missing(key: &str) -> LibcryptErr {
+    invalid(format!("LUKS2 field {key} is missing"))
+}
+
+/// Parse a decimal string holding a keyslot, token, segment or digest id.
+/// Like libcryptsetup, non-canonical forms such as `"01"` are accepted; ids
+/// are written back in canonical form.
+pub(crate) fn parse_id(s: &str) -> Result<c_uint, LibcryptErr> {
+    s.parse::<c_uint>()
+        .map_err(|_| invalid(format!("LUKS2 id \"{s}\" is not a decimal id")))
+}
+
+/// Parse an array of ids stored as decimal strings
+pub(crate) fn parse_id_array(key: &str, value: Value) -> Result<Vec<c_uint>, LibcryptErr> {
+    match value {
+        Value::Array(a) => a
+            .iter()
+            .map(|v| match v.as_str() {
+                Some(s) => parse_id(s),
+                None => Err(invalid(format!("LUKS2 field {key} contains {v}"))),
+            })
+            .collect(),
+        v => Err(invalid(format!("LUKS2 field {key} is not an array: {v}"))),
+    }
+}
+
+pub(crate) fn take_id_array(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Vec<c_uint>, LibcryptErr> {
+    parse_id_array(key, map.remove(key).ok_or_else(|| missing(key))?)
+}
+
+pub(crate) fn take_string(map: &mut Map<String, Value>, key: &str) -> Result<String, LibcryptErr> {
+    take_opt_string(map, key)?.ok_or_else(|| missing(key))
+}
+
+pub(crate) fn take_opt_string(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<String>, LibcryptErr> {
+    match map.remove(key) {
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(v) => Err(invalid(format!("LUKS2 field {key} is not a string: {v}"))),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn take_opt_bool(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<bool>, LibcryptErr> {
+    match map.remove(key) {
+        Some(Value::Bool(b)) => Ok(Some(b)),
+        Some(v) => Err(invalid(format!("LUKS2 field {key} is not a boolean: {v}"))),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn take_opt_u32(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<u32>, LibcryptErr> {
+    match map.remove(key) {
+        Some(v) => v
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .map(Some)
+            .ok_or_else(|| invalid(format!("LUKS2 field {key} is not a 32 bit integer: {v}"))),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn take_u32(map: &mut Map<String, Value>, key: &str) -> Result<u32, LibcryptErr> {
+    take_opt_u32(map, key)?.ok_or_else(|| missing(key))
+}
+
+pub(crate) fn take_opt_i32(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<i32>, LibcryptErr> {
+    match map.remove(key) {
+        Some(v) => v
+            .as_i64()
+            .and_then(|n| i32::try_from(n).ok())
+            .map(Some)
+            .ok_or_else(|| invalid(format!("LUKS2 field {key} is not a 32 bit integer: {v}"))),
+        None => Ok(None),
+    }
+}
+
+/// Take a 64 bit integer stored as a decimal string, as LUKS2 does for offsets
+/// and sizes
+pub(crate) fn take_opt_u64_string(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<u64>, LibcryptErr> {
+    match take_opt_string(map, key)? {
+        Some(s) => s
+            .parse::<u64>()
+            .ok()
+            .filter(|n| n.to_string() == s)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("LUKS2 field {key} is not a decimal string: {s}"))),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn take_u64_string(map: &mut Map<String, Value>, key: &str) -> Result<u64, LibcryptErr> {
+    take_opt_u64_string(map, key)?.ok_or_else(|| missing(key))
+}
+
+pub(crate) fn take_u32_array(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Vec<u32>, LibcryptErr> {
+    match map.remove(key) {
+        Some(Value::Array(a)) => a
+            .iter()
+            .map(|v| {
+                v.as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| invalid(format!("LUKS2 field {key} contains {v}")))
+            })
+            .collect(),
+        Some(v) => Err(invalid(format!("LUKS2 field {key} is not an array: {v}"))),
+        None => Err(missing(key)),
+    }
+}
+
+pub(crate) fn take_opt_string_array(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<Vec<String>>, LibcryptErr> {
+    match map.remove(key) {
+        Some(Value::Array(a)) => a
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => Ok(s),
+                v => Err(invalid(format!("LUKS2 field {key} contains {v}"))),
+            })
+            .collect::<Result<_, _>>()
+            .map(Some),
+        Some(v) => Err(invalid(format!("LUKS2 field {key} is not an array: {v}"))),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn take_opt_object(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<Map<String, Value>>, LibcryptErr> {
+    match map.remove(key) {
+        Some(Value::Object(o)) => Ok(Some(o)),
+        Some(v) => Err(invalid(format!("LUKS2 field {key} is not an object: {v}"))),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn take_object(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Map<String, Value>, LibcryptErr> {
+    take_opt_object(map, key)?.ok_or_else(|| missing(key))
+}
+
+pub(crate) fn put_opt<T: Into<Value>>(map: &mut Map<String, Value>, key: &str, value: Option<T>) {
+    if let Some(v) = value {
+        map.insert(key.to_string(), v.into());
+    }
+}
@@ -0,0 +1,500 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{collections::BTreeMap, path::Path};
+
+use libc::c_uint;
+use serde_json::{Map, Value};
+
+use crate::{
+    err::LibcryptErr,
+    luks2::{
+        header::Luks2OnDiskHeader,
+        json::{
+            invalid, parse_id, take_id_array, take_opt_i32, take_opt_object, take_opt_string,
+            take_opt_string_array, take_opt_u32, take_opt_u64_string, take_string, take_u32,
+            take_u64_string,
+        },
+        token_types::Luks2Token,
+    },
+};
+
+/// Anti-forensic splitter settings of a keyslot
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2KeyslotAf {
+    /// Type of the splitter, always `luks1`
+    pub type_: String,
+    /// Number of stripes
+    pub stripes: u32,
+    /// Hash used by the splitter
+    pub hash: String,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// Key derivation settings of a keyslot
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2KeyslotKdf {
+    /// Type of the KDF: `pbkdf2`, `argon2i` or `argon2id`
+    pub type_: String,
+    /// Base64 encoded salt
+    pub salt: String,
+    /// Hash used by PBKDF2
+    pub hash: Option<String>,
+    /// Iterations of PBKDF2
+    pub iterations: Option<u32>,
+    /// Time cost of Argon2
+    pub time: Option<u32>,
+    /// Memory cost of Argon2 in KiB
+    pub memory: Option<u32>,
+    /// Parallel threads of Argon2
+    pub cpus: Option<u32>,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// Area of the header holding the keyslot data
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2KeyslotArea {
+    /// Type of the area: `raw` for key material or `none`, `journal`,
+    /// `checksum` or `datashift` for reencryption
+    pub type_: String,
+    /// Offset of the area in bytes from the start of the device
+    pub offset: Option<u64>,
+    /// Size of the area in bytes
+    pub size: Option<u64>,
+    /// Cipher used to encrypt the key material
+    pub encryption: Option<String>,
+    /// Size in bytes of the key encrypting the key material
+    pub key_size: Option<u32>,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// LUKS2 keyslot
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2Keyslot {
+    /// Type of the keyslot: `luks2` or `reencrypt`
+    pub type_: String,
+    /// Size in bytes of the stored key
+    pub key_size: u32,
+    /// Priority of the keyslot
+    pub priority: Option<i32>,
+    /// Area holding the keyslot data
+    pub area: Luks2KeyslotArea,
+    /// Key derivation settings; absent for reencryption keyslots
+    pub kdf: Option<Luks2KeyslotKdf>,
+    /// Anti-forensic splitter settings; absent for reencryption keyslots
+    pub af: Option<Luks2KeyslotAf>,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// LUKS2 data segment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2Segment {
+    /// Type of the segment: `crypt` or `linear`
+    pub type_: String,
+    /// Offset of the segment in bytes from the start of the data device
+    pub offset: u64,
+    /// Size of the segment in bytes or `None` if it extends to the end of the
+    /// device
+    pub size: Option<u64>,
+    /// Starting IV offset in sectors
+    pub iv_tweak: Option<u64>,
+    /// Cipher of the segment
+    pub encryption: Option<String>,
+    /// Sector size of the segment in bytes
+    pub sector_size: Option<u32>,
+    /// Integrity settings of the segment
+    pub integrity: Option<Map<String, Value>>,
+    /// Segment flags such as `backup-final` or `in-reencryption`
+    pub flags: Vec<String>,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// LUKS2 volume key digest
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2Digest {
+    /// Type of the digest, always `pbkdf2`
+    pub type_: String,
+    /// Keyslots holding the volume key verified by this digest
+    pub keyslots: Vec<c_uint>,
+    /// Segments encrypted with the volume key verified by this digest
+    pub segments: Vec<c_uint>,
+    /// Hash of the digest
+    pub hash: Option<String>,
+    /// Iterations of the digest
+    pub iterations: Option<u32>,
+    /// Base64 encoded salt
+    pub salt: String,
+    /// Base64 encoded digest
+    pub digest: String,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// LUKS2 persistent configuration
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2Config {
+    /// Size in bytes of the JSON area
+    pub json_size: u64,
+    /// Size in bytes of the keyslots area
+    pub keyslots_size: u64,
+    /// Persistent activation flags such as `allow-discards`
+    pub flags: Vec<String>,
+    /// Mandatory requirements such as `online-reencrypt-v2`
+    pub requirements: Vec<String>,
+    /// Unrecognized fields
+    pub extensions: Map<String, Value>,
+}
+
+/// Typed LUKS2 metadata as returned by `CryptDeviceStatusHandle::dump_json`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2Metadata {
+    /// Keyslots by id
+    pub keyslots: BTreeMap<c_uint, Luks2Keyslot>,
+    /// Tokens by id
+    pub tokens: BTreeMap<c_uint, Luks2Token>,
+    /// Segments by id
+    pub segments: BTreeMap<c_uint, Luks2Segment>,
+    /// Digests by id
+    pub digests: BTreeMap<c_uint, Luks2Digest>,
+    /// Persistent configuration
+    pub config: Luks2Config,
+}
+
+fn into_object(key: &str, value: Value) -> Result<Map<String, Value>, LibcryptErr> {
+    match value {
+        Value::Object(o) => Ok(o),
+        v => Err(invalid(format!("LUKS2 {key} is not an object: {v}"))),
+    }
+}
+
+fn parse_section<T>(
+    metadata: &mut Map<String, Value>,
+    key: &str,
+    parse: impl Fn(Map<String, Value>) -> Result<T, LibcryptErr>,
+) -> Result<BTreeMap<c_uint, T>, LibcryptErr> {
+    let section = metadata
+        .remove(key)
+        .ok_or_else(|| invalid(format!("LUKS2 metadata has no {key} section")))?;
+    into_object(key, section)?
+        .into_iter()
+        .map(|(id, value)| Ok((parse_id(&id)?, parse(into_object(key, value)?)?)))
+        .collect()
+}
+
+fn parse_af(mut map: Map<String, Value>) -> Result<Luks2KeyslotAf, LibcryptErr> {
+    Ok(Luks2KeyslotAf {
+        type_: take_string(&mut map, "type")?,
+        stripes: take_u32(&mut map, "stripes")?,
+        hash: take_string(&mut map, "hash")?,
+        extensions: map,
+    })
+}
+
+fn parse_kdf(mut map: Map<String, Value>) -> Result<Luks2KeyslotKdf, LibcryptErr> {
+    Ok(Luks2KeyslotKdf {
+        type_: take_string(&mut map, "type")?,
+        salt: take_string(&mut map, "salt")?,
+        hash: take_opt_string(&mut map, "hash")?,
+        iterations: take_opt_u32(&mut map, "iterations")?,
+        time: take_opt_u32(&mut map, "time")?,
+        memory: take_opt_u32(&mut map, "memory")?,
+        cpus: take_opt_u32(&mut map, "cpus")?,
+        extensions: map,
+    })
+}
+
+fn parse_area(mut map: Map<String, Value>) -> Result<Luks2KeyslotArea, LibcryptErr> {
+    Ok(Luks2KeyslotArea {
+        type_: take_string(&mut map, "type")?,
+        offset: take_opt_u64_string(&mut map, "offset")?,
+        size: take_opt_u64_string(&mut map, "size")?,
+        encryption: take_opt_string(&mut map, "encryption")?,
+        key_size: take_opt_u32(&mut map, "key_size")?,
+        extensions: map,
+    })
+}
+
+fn parse_keyslot(mut map: Map<String, Value>) -> Result<Luks2Keyslot, LibcryptErr> {
+    Ok(Luks2Keyslot {
+        type_: take_string(&mut map, "type")?,
+        key_size: take_u32(&mut map, "key_size")?,
+        priority: take_opt_i32(&mut map, "priority")?,
+        area: match take_opt_object(&mut map, "area")? {
+            Some(area) => parse_area(area)?,
+            None => return Err(invalid("LUKS2 keyslot has no area".to_string())),
+        },
+        kdf: take_opt_object(&mut map, "kdf")?
+            .map(parse_kdf)
+            .transpose()?,
+        af: take_opt_object(&mut map, "af")?.map(parse_af).transpose()?,
+        extensions: map,
+    })
+}
+
+fn parse_segment(mut map: Map<String, Value>) -> Result<Luks2Segment, LibcryptErr> {
+    let type_ = take_string(&mut map, "type")?;
+    let offset = take_u64_string(&mut map, "offset")?;
+    let size = match map.get("size") {
+        Some(Value::String(s)) if s == "dynamic" => {
+            map.remove("size");
+            None
+        }
+        _ => Some(take_u64_string(&mut map, "size")?),
+    };
+    Ok(Luks2Segment {
+        type_,
+        offset,
+        size,
+        iv_tweak: take_opt_u64_string(&mut map, "iv_tweak")?,
+        encryption: take_opt_string(&mut map, "encryption")?,
+        sector_size: take_opt_u32(&mut map, "sector_size")?,
+        integrity: take_opt_object(&mut map, "integrity")?,
+        flags: take_opt_string_array(&mut map, "flags")?.unwrap_or_default(),
+        extensions: map,
+    })
+}
+
+fn parse_digest(mut map: Map<String, Value>) -> Result<Luks2Digest, LibcryptErr> {
+    Ok(Luks2Digest {
+        type_: take_string(&mut map, "type")?,
+        keyslots: take_id_array(&mut map, "keyslots")?,
+        segments: take_id_array(&mut map, "segments")?,
+        hash: take_opt_string(&mut map, "hash")?,
+        iterations: take_opt_u32(&mut map, "iterations")?,
+        salt: take_string(&mut map, "salt")?,
+        digest: take_string(&mut map, "digest")?,
+        extensions: map,
+    })
+}
+
+fn parse_config(mut map: Map<String, Value>) -> Result<Luks2Config, LibcryptErr> {
+    let requirements = match take_opt_object(&mut map, "requirements")? {
+        Some(mut r) => {
+            let mandatory = take_opt_string_array(&mut r, "mandatory")?.unwrap_or_default();
+            if !r.is_empty() {
+                map.insert("requirements".to_string(), Value::Object(r));
+            }
+            mandatory
+        }
+        None => Vec::new(),
+    };
+    Ok(Luks2Config {
+        json_size: take_u64_string(&mut map, "json_size")?,
+        keyslots_size: take_u64_string(&mut map, "keyslots_size")?,
+        flags: take_opt_string_array(&mut map, "flags")?.unwrap_or_default(),
+        requirements,
+        extensions: map,
+    })
+}
+
+impl TryFrom<&Value> for Luks2Metadata {
+    type Error = LibcryptErr;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        let mut metadata = into_object("metadata", v.clone())?;
+        let tokens = parse_section(&mut metadata, "tokens", |t| {
+            Luks2Token::try_from(&Value::Object(t))
+        })?;
+        Ok(Luks2Metadata {
+            keyslots: parse_section(&mut metadata, "keyslots", parse_keyslot)?,
+            tokens,
+            segments: parse_section(&mut metadata, "segments", parse_segment)?,
+            digests: parse_section(&mut metadata, "digests", parse_digest)?,
+            config: match metadata.remove("config") {
+                Some(c) => parse_config(into_object("config", c)?)?,
+                None => return Err(invalid("LUKS2 metadata has no config section".to_string())),
+            },
+        })
+    }
+}
+
+impl Luks2Metadata {
+    /// Parse the JSON metadata of the newest valid header copy in a LUKS2
+    /// header backup file or device
+    pub fn from_header_file(path: &Path) -> Result<Self, LibcryptErr> {
+        Luks2OnDiskHeader::from_file(path)?.metadata()
+    }
+
+    /// Get the id and the digest of the volume key that encrypts `segment`
+    pub fn segment_digest(&self, segment: c_uint) -> Option<(c_uint, &Luks2Digest)> {
+        self.digests
+            .iter()
+            .find(|(_, d)| d.segments.contains(&segment))
+            .map(|(id, d)| (*id, d))
+    }
+
+    /// Get the keyslots holding the volume key that encrypts `segment`
+    pub fn segment_keyslots(&self, segment: c_uint) -> Vec<c_uint> {
+        self.segment_digest(segment)
+            .map(|(_, d)| {
+                d.keyslots
+                    .iter()
+                    .filter(|k| self.keyslots.contains_key(k))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the id of the digest verifying the key stored in `keyslot`
+    pub fn keyslot_digest(&self, keyslot: c_uint) -> Option<c_uint> {
+        self.digests
+            .iter()
+            .find(|(_, d)| d.keyslots.contains(&keyslot))
+            .map(|(id, _)| *id)
+    }
+
+    /// Get the tokens assigned to `keyslot`
+    pub fn keyslot_tokens(&self, keyslot: c_uint) -> Vec<c_uint> {
+        self.tokens
+            .iter()
+            .filter(|(_, t)| t.keyslots.contains(&keyslot))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Check whether an online reencryption is in progress
+    pub fn is_reencryption_in_progress(&self) -> bool {
+        self.config
+            .requirements
+            .iter()
+            .any(|r| r.starts_with("online-reencrypt"))
+            || self.keyslots.values().any(|k| k.type_ == "reencrypt")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    fn metadata() -> Value {
+        json!({
+            "keyslots": {
+                "0": {
+                    "type": "luks2",
+                    "key_size": 64,
+                    "af": {"type": "luks1", "stripes": 4000, "hash": "sha256"},
+                    "area": {
+                        "type": "raw",
+                        "offset": "32768",
+                        "size": "258048",
+                        "encryption": "aes-xts-plain64",
+                        "key_size": 64
+                    },
+                    "kdf": {
+                        "type": "argon2id",
+                        "time": 4,
+                        "memory": 1048576,
+                        "cpus": 4,
+                        "salt": "c2FsdA=="
+                    }
+                },
+                "1": {
+                    "type": "luks2",
+                    "key_size": 64,
+                    "priority": 2,
+                    "af": {"type": "luks1", "stripes": 4000, "hash": "sha256"},
+                    "area": {
+                        "type": "raw",
+                        "offset": "290816",
+                        "size": "258048",
+                        "encryption": "aes-xts-plain64",
+                        "key_size": 64
+                    },
+                    "kdf": {
+                        "type": "pbkdf2",
+                        "hash": "sha256",
+                        "iterations": 1000,
+                        "salt": "c2FsdA=="
+                    }
+                }
+            },
+            "tokens": {
+                "0": {"type": "luks2-keyring", "keyslots": ["1"], "key_description": "key"}
+            },
+            "segments": {
+                "0": {
+                    "type": "crypt",
+                    "offset": "16777216",
+                    "size": "dynamic",
+                    "iv_tweak": "0",
+                    "encryption": "aes-xts-plain64",
+                    "sector_size": 512
+                }
+            },
+            "digests": {
+                "0": {
+                    "type": "pbkdf2",
+                    "keyslots": ["0", "1"],
+                    "segments": ["0"],
+                    "hash": "sha256",
+                    "iterations": 1000,
+                    "salt": "c2FsdA==",
+                    "digest": "ZGlnZXN0"
+                }
+            },
+            "config": {
+                "json_size": "12288",
+                "keyslots_size": "16744448",
+                "flags": ["allow-discards"]
+            }
+        })
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = Luks2Metadata::try_from(&metadata()).unwrap();
+        assert_eq!(metadata.keyslots.len(), 2);
+        let keyslot = &metadata.keyslots[&0];
+        assert_eq!(keyslot.area.offset, Some(32768));
+        assert_eq!(keyslot.kdf.as_ref().unwrap().memory, Some(1048576));
+        assert_eq!(metadata.keyslots[&1].priority, Some(2));
+        assert_eq!(metadata.segments[&0].size, None);
+        assert_eq!(metadata.segments[&0].offset, 16777216);
+        assert_eq!(metadata.config.flags, vec!["allow-discards".to_string()]);
+        assert!(metadata.config.requirements.is_empty());
+
+        assert_eq!(metadata.segment_digest(0).map(|(id, _)| id), Some(0));
+        assert_eq!(metadata.segment_digest(1).map(|(id, _)| id), None);
+        assert_eq!(metadata.segment_keyslots(0), vec![0, 1]);
+        assert_eq!(metadata.keyslot_digest(1), Some(0));
+        assert_eq!(metadata.keyslot_tokens(1), vec![0]);
+        assert!(metadata.keyslot_tokens(0).is_empty());
+        assert!(!metadata.is_reencryption_in_progress());
+    }
+
+    #[test]
+    fn test_reencryption_in_progress() {
+        let mut value = metadata();
+        value["config"]["requirements"] = json!({"mandatory": ["online-reencrypt-v2"]});
+        let metadata = Luks2Metadata::try_from(&value).unwrap();
+        assert_eq!(
+            metadata.config.requirements,
+            vec!["online-reencrypt-v2".to_string()]
+        );
+        assert!(metadata.is_reencryption_in_progress());
+    }
+
+    #[test]
+    fn test_invalid_metadata() {
+        let mut value = metadata();
+        value["segments"]["0"]["offset"] = json!(16777216);
+        assert!(Luks2Metadata::try_from(&value).is_err());
+
+        let mut value = metadata();
+        value["keyslots"]["x"] = value["keyslots"]["0"].clone();
+        assert!(Luks2Metadata::try_from(&value).is_err());
+
+        let mut value = metadata();
+        value.as_object_mut().unwrap().remove("config");
+        assert!(Luks2Metadata::try_from(&value).is_err());
+    }
+}
@@ -1,3 +1,7 @@
 pub mod flags;
+pub mod header;
+mod json;
+pub mod metadata;
 pub mod reencrypt;
 pub mod token;
+pub mod token_types;
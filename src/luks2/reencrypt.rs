@@ -7,9 +7,20 @@ use std::{
     os::raw::{c_int, c_uint, c_void},
     ptr,
 };
+#[cfg(cryptsetup24supported)]
+use std::{
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use libcryptsetup_rs_sys::{crypt_params_reencrypt, CRYPT_ANY_SLOT};
 
+#[cfg(cryptsetup24supported)]
+use crate::callback::{progress_trampoline, ProgressState};
 use crate::{
     consts::{
         flags::CryptReencrypt,
@@ -101,6 +112,121 @@ impl<'a> TryInto<CryptParamsReencryptRef<'a>> for &'a CryptParamsReencrypt {
     }
 }
 
+/// Progress of a `ReencryptJob`
+#[cfg(cryptsetup24supported)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReencryptJobProgress {
+    /// Bytes reencrypted so far, including those reencrypted before the job
+    /// was resumed
+    pub bytes_done: u64,
+    /// Total number of bytes to reencrypt
+    pub total: u64,
+    /// Bytes reencrypted per second since the job started, once measurable
+    pub throughput: Option<u64>,
+    /// Estimated time remaining at the current throughput
+    pub eta: Option<Duration>,
+}
+
+#[cfg(cryptsetup24supported)]
+impl ReencryptJobProgress {
+    fn new(total: u64, bytes_done: u64, start_offset: u64, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let throughput = if secs > 0.0 {
+            Some((bytes_done.saturating_sub(start_offset) as f64 / secs) as u64)
+        } else {
+            None
+        }
+        .filter(|t| *t > 0);
+        let eta = throughput
+            .map(|t| Duration::from_secs_f64(total.saturating_sub(bytes_done) as f64 / t as f64));
+        ReencryptJobProgress {
+            bytes_done,
+            total,
+            throughput,
+            eta,
+        }
+    }
+}
+
+/// Token that cancels a `ReencryptJob`, possibly from another thread.
+///
+/// Once cancelled, a token stays cancelled.
+#[cfg(cryptsetup24supported)]
+#[derive(Clone, Debug, Default)]
+pub struct ReencryptCancelToken(Arc<AtomicBool>);
+
+#[cfg(cryptsetup24supported)]
+impl ReencryptCancelToken {
+    /// Request that the job stop at the next progress report
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Check whether cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Reencryption of a device initialized with one of the
+/// `CryptLuks2ReencryptHandle::reencrypt_init_*` methods
+#[cfg(cryptsetup24supported)]
+pub struct ReencryptJob<'a> {
+    reference: &'a mut CryptDevice,
+    cancel: ReencryptCancelToken,
+}
+
+#[cfg(cryptsetup24supported)]
+impl ReencryptJob<'_> {
+    /// Get a token that cancels this job
+    pub fn cancel_token(&self) -> ReencryptCancelToken {
+        self.cancel.clone()
+    }
+
+    /// Run the reencryption, reporting progress to a closure, until it
+    /// completes or the job is cancelled.
+    ///
+    /// Returns the reencryption state of the device afterwards:
+    /// `CryptReencryptInfo::None` once reencryption has completed and
+    /// `CryptReencryptInfo::Clean` if it was cancelled and can be resumed by
+    /// initializing with `CryptReencrypt::RESUME_ONLY` and running a new job.
+    pub fn run<F>(&mut self, mut progress: F) -> Result<CryptReencryptInfo, LibcryptErr>
+    where
+        F: FnMut(&ReencryptJobProgress),
+    {
+        let cancel = &self.cancel;
+        let mut start = None;
+        let mut report = |size, offset| {
+            let (started, start_offset) = *start.get_or_insert_with(|| (Instant::now(), offset));
+            progress(&ReencryptJobProgress::new(
+                size,
+                offset,
+                start_offset,
+                started.elapsed(),
+            ));
+            if cancel.is_cancelled() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        };
+        let mut state = ProgressState::new(&mut report);
+        let rc = mutex!(libcryptsetup_rs_sys::crypt_reencrypt_run(
+            self.reference.as_ptr(),
+            Some(progress_trampoline),
+            state.as_ptr(),
+        ));
+        errno!(state.finish(rc))?;
+        try_int_to_return!(
+            mutex!(libcryptsetup_rs_sys::crypt_reencrypt_status(
+                self.reference.as_ptr(),
+                ptr::null_mut(),
+            )),
+            CryptReencryptInfo
+        )
+    }
+}
+
 /// Handle for reencryption operations
 pub struct CryptLuks2ReencryptHandle<'a> {
     reference: &'a mut CryptDevice,
@@ -242,6 +368,36 @@ impl<'a> CryptLuks2ReencryptHandle<'a> {
         )))
     }
 
+    /// Run data reencryption, reporting progress to a closure.
+    ///
+    /// The closure receives the total size and the number of bytes
+    /// reencrypted so far. Returning `ControlFlow::Break` interrupts the
+    /// reencryption, which can be resumed later. A panic in the closure also
+    /// interrupts it and is resumed once libcryptsetup has returned.
+    #[cfg(cryptsetup24supported)]
+    pub fn reencrypt_with_progress<F>(&mut self, mut progress: F) -> Result<(), LibcryptErr>
+    where
+        F: FnMut(u64, u64) -> ControlFlow<()>,
+    {
+        let mut state = ProgressState::new(&mut progress);
+        let rc = mutex!(libcryptsetup_rs_sys::crypt_reencrypt_run(
+            self.reference.as_ptr(),
+            Some(progress_trampoline),
+            state.as_ptr(),
+        ));
+        errno!(state.finish(rc))
+    }
+
+    /// Create a cancellable job running the reencryption initialized on this
+    /// device
+    #[cfg(cryptsetup24supported)]
+    pub fn job(self) -> ReencryptJob<'a> {
+        ReencryptJob {
+            reference: self.reference,
+            cancel: ReencryptCancelToken::default(),
+        }
+    }
+
     /// LUKS2 reencryption status
     pub fn status(
         &mut self,
@@ -257,3 +413,34 @@ impl<'a> CryptLuks2ReencryptHandle<'a> {
         )
     }
 }
+
+#[cfg(all(test, cryptsetup24supported))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reencrypt_job_progress() {
+        let progress = ReencryptJobProgress::new(100, 10, 10, Duration::ZERO);
+        assert_eq!(progress.throughput, None);
+        assert_eq!(progress.eta, None);
+
+        // Resumed at 10 bytes, 40 bytes reencrypted in 2 seconds.
+        let progress = ReencryptJobProgress::new(100, 50, 10, Duration::from_secs(2));
+        assert_eq!(progress.bytes_done, 50);
+        assert_eq!(progress.total, 100);
+        assert_eq!(progress.throughput, Some(20));
+        assert_eq!(progress.eta, Some(Duration::from_millis(2500)));
+
+        let progress = ReencryptJobProgress::new(100, 100, 0, Duration::from_secs(4));
+        assert_eq!(progress.eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_reencrypt_cancel_token() {
+        let token = ReencryptCancelToken::default();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+        std::thread::spawn(move || clone.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+    }
+}
@@ -2,11 +2,23 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::ptr;
+use std::{
+    mem::ManuallyDrop,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice, thread,
+};
 
-use crate::{consts::flags::CryptActivate, device::CryptDevice, err::LibcryptErr};
+#[cfg(cryptsetup27supported)]
+use std::path::Path;
+#[cfg(cryptsetup24supported)]
+use std::path::PathBuf;
 
-use libc::{c_char, c_int, c_uint, c_void};
+use crate::{
+    consts::flags::CryptActivate, device::CryptDevice, err::LibcryptErr,
+    luks2::token_types::Luks2Token,
+};
+
+use libc::{c_char, c_int, c_uint, c_void, size_t};
 
 /// Type representing the token status. This type wraps the `CRYPT_TOKEN_*` values and the optional corresponding token type as a string.
 pub enum CryptTokenInfo {
@@ -79,6 +91,18 @@ pub enum TokenInput<'a> {
     RemoveToken(c_uint),
 }
 
+/// Token in use in the LUKS2 header, as yielded by `CryptLuks2TokenHandle::iter`
+pub struct CryptTokenEntry {
+    /// Token id
+    pub id: c_uint,
+    /// Token status and type
+    pub info: CryptTokenInfo,
+    /// Contents of the token
+    pub json: serde_json::Value,
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+}
+
 /// Handle for LUKS2 token operations
 pub struct CryptLuks2TokenHandle<'a> {
     reference: &'a mut CryptDevice,
@@ -127,6 +151,59 @@ impl<'a> CryptLuks2TokenHandle<'a> {
         .map(|rc| rc as c_uint)
     }
 
+    /// Get a token and check it against the LUKS2 token rules
+    pub fn get(&mut self, token: c_uint) -> Result<Luks2Token, LibcryptErr> {
+        Luks2Token::try_from(&self.json_get(token)?)
+    }
+
+    /// Validate a token and write it to the header.
+    ///
+    /// A value of `None` for `token` adds the token to any free slot. Returns
+    /// the token id.
+    pub fn set(
+        &mut self,
+        token: Option<c_uint>,
+        value: &Luks2Token,
+    ) -> Result<c_uint, LibcryptErr> {
+        let json = value.to_json()?;
+        self.json_set(match token {
+            Some(t) => TokenInput::ReplaceToken(t, &json),
+            None => TokenInput::AddToken(&json),
+        })
+    }
+
+    /// Get the maximum number of tokens in a LUKS2 header
+    #[cfg(cryptsetup24supported)]
+    pub fn max(&mut self) -> Result<c_uint, LibcryptErr> {
+        errno_int_success!(mutex!(libcryptsetup_rs_sys::crypt_token_max(
+            crate::consts::vals::EncryptionFormat::Luks2.as_ptr()
+        )))
+        .map(|rc| rc as c_uint)
+    }
+
+    /// Iterate over all tokens in use together with their assigned keyslots
+    #[cfg(cryptsetup24supported)]
+    pub fn iter(&mut self) -> Result<CryptTokenIter<'_, 'a>, LibcryptErr> {
+        let max = self.max()?;
+        Ok(CryptTokenIter {
+            handle: self,
+            next: 0,
+            max,
+        })
+    }
+
+    /// Get the ids of all token slots that are not in use
+    #[cfg(cryptsetup24supported)]
+    pub fn free_slots(&mut self) -> Result<Vec<c_uint>, LibcryptErr> {
+        let mut free = Vec::new();
+        for token in 0..self.max()? {
+            if let CryptTokenInfo::Inactive = self.status(token)? {
+                free.push(token);
+            }
+        }
+        Ok(free)
+    }
+
     /// Get the token info for a specific token
     pub fn status(&mut self, token: c_uint) -> Result<CryptTokenInfo, LibcryptErr> {
         let mut ptr: *const c_char = std::ptr::null();
@@ -222,10 +299,10 @@ impl<'a> CryptLuks2TokenHandle<'a> {
         ));
         if rc == 0 {
             Ok(true)
-        } else if rc == libc::ENOENT {
+        } else if rc == -libc::ENOENT {
             Ok(false)
         } else {
-            Err(LibcryptErr::IOError(std::io::Error::from_raw_os_error(-rc)))
+            Err(LibcryptErr::from_errno(-rc))
         }
     }
 
@@ -307,7 +384,288 @@ impl<'a> CryptLuks2TokenHandle<'a> {
     }
 }
 
+/// Iterator over the tokens in use in a LUKS2 header
+#[cfg(cryptsetup24supported)]
+pub struct CryptTokenIter<'b, 'a> {
+    handle: &'b mut CryptLuks2TokenHandle<'a>,
+    next: c_uint,
+    max: c_uint,
+}
+
+#[cfg(cryptsetup24supported)]
+impl Iterator for CryptTokenIter<'_, '_> {
+    type Item = Result<CryptTokenEntry, LibcryptErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.max {
+            let id = self.next;
+            self.next += 1;
+            let info = match self.handle.status(id) {
+                Ok(CryptTokenInfo::Invalid) | Ok(CryptTokenInfo::Inactive) => continue,
+                Ok(info) => info,
+                Err(e) => return Some(Err(e)),
+            };
+            return Some(self.handle.json_get(id).and_then(|json| {
+                let keyslots = Luks2Token::try_from(&json)?.keyslots;
+                Ok(CryptTokenEntry {
+                    id,
+                    info,
+                    json,
+                    keyslots,
+                })
+            }));
+        }
+        None
+    }
+}
+
+/// Get the directory from which external token plugins are loaded.
+///
+/// Returns `None` if loading of external token plugins is disabled.
+#[cfg(cryptsetup24supported)]
+pub fn get_token_external_path() -> Result<Option<PathBuf>, LibcryptErr> {
+    match ptr_to_option!(mutex!(libcryptsetup_rs_sys::crypt_token_external_path())) {
+        Some(p) => from_str_ptr!(p).map(|s| Some(PathBuf::from(s))),
+        None => Ok(None),
+    }
+}
+
+/// Set the directory from which external token plugins are loaded, or restore
+/// the default directory with `None`.
+///
+/// The setting is global to the process and applies to every device. It only
+/// affects token plugins that have not been loaded yet so it should be set
+/// before any token operation. `path` must be an absolute path to an existing
+/// directory. libcryptsetup returns `ENOTSUP` if it was built without support
+/// for external tokens or if they were disabled with
+/// [`disable_token_external`].
+#[cfg(cryptsetup27supported)]
+pub fn set_token_external_path(path: Option<&Path>) -> Result<(), LibcryptErr> {
+    let path_cstring = match path {
+        Some(p) => {
+            if !p.is_absolute() {
+                return Err(LibcryptErr::Other(format!(
+                    "Token plugin path {} is not absolute",
+                    p.display()
+                )));
+            }
+            if !p.is_dir() {
+                return Err(LibcryptErr::Other(format!(
+                    "Token plugin path {} is not a directory",
+                    p.display()
+                )));
+            }
+            Some(path_to_cstring!(p)?)
+        }
+        None => None,
+    };
+    errno!(mutex!(libcryptsetup_rs_sys::crypt_token_set_external_path(
+        path_cstring
+            .as_ref()
+            .map(|s| s.as_ptr())
+            .unwrap_or_else(ptr::null),
+    )))
+}
+
+/// Disable loading of external token plugins.
+///
+/// The setting is global to the process and cannot be reverted. Plugins that
+/// were already loaded stay in use, so call this before any token operation,
+/// for example at the start of a service that must only use the built-in
+/// token handlers.
+#[cfg(cryptsetup24supported)]
+pub fn disable_token_external() {
+    mutex!(libcryptsetup_rs_sys::crypt_token_external_disable())
+}
+
+/// Safe interface for a LUKS2 token handler.
+///
+/// The handler is stateless: libcryptsetup invokes the associated functions
+/// through trampolines generated by [`register_handler`]. Errors are reported to
+/// libcryptsetup as the negative of `LibcryptErr::errno` or as `-EINVAL` for
+/// errors without an errno and panics are caught before they reach the FFI boundary.
+pub trait TokenHandler {
+    /// Type of the token, as it appears in the `type` field of the token JSON
+    const NAME: &'static str;
+
+    /// Type of the user data passed to `activate_by_token` and
+    /// `activate_by_token_pin`. libcryptsetup passes the user data through an
+    /// untyped pointer so the caller must pass a reference of this type.
+    type UserData;
+
+    /// Return the passphrase unlocking the keyslots assigned to `token`
+    fn open(
+        device: &mut CryptDevice,
+        token: c_uint,
+        usrdata: Option<&mut Self::UserData>,
+    ) -> Result<Box<[u8]>, LibcryptErr>;
+
+    /// Return the passphrase unlocking the keyslots assigned to `token` using
+    /// `pin`.
+    ///
+    /// libcryptsetup only calls this for handlers loaded from external token
+    /// plugins exported with [`export_token_plugin!`](crate::export_token_plugin).
+    /// The default implementation ignores the PIN.
+    fn open_pin(
+        device: &mut CryptDevice,
+        token: c_uint,
+        pin: &[u8],
+        usrdata: Option<&mut Self::UserData>,
+    ) -> Result<Box<[u8]>, LibcryptErr> {
+        let _ = pin;
+        Self::open(device, token, usrdata)
+    }
+
+    /// Validate the token JSON before it is stored in the header. The default
+    /// implementation accepts any JSON.
+    fn validate(device: &mut CryptDevice, json: &serde_json::Value) -> Result<(), LibcryptErr> {
+        let _ = (device, json);
+        Ok(())
+    }
+
+    /// Print handler specific information about the token. The default
+    /// implementation prints nothing.
+    fn dump(device: &mut CryptDevice, json: &serde_json::Value) {
+        let _ = (device, json);
+    }
+}
+
+fn token_handler_rc(result: thread::Result<Result<(), LibcryptErr>>) -> c_int {
+    match result {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => -e.errno().unwrap_or(libc::EINVAL),
+        Err(_) => -libc::EINVAL,
+    }
+}
+
+fn token_handler_json(json: *const c_char) -> Result<serde_json::Value, LibcryptErr> {
+    let ptr = ptr_to_result!(json)?;
+    from_str_ptr!(ptr).and_then(|s| serde_json::from_str(s).map_err(LibcryptErr::JsonError))
+}
+
+fn token_handler_buffer(
+    result: Result<Box<[u8]>, LibcryptErr>,
+    buffer: *mut *mut c_char,
+    buffer_len: *mut size_t,
+) -> Result<(), LibcryptErr> {
+    let passphrase = result?;
+    let len = passphrase.len();
+    unsafe {
+        *buffer = Box::into_raw(passphrase).cast::<c_char>();
+        *buffer_len = len;
+    }
+    Ok(())
+}
+
+/// # Safety
+///
+/// `buffer` and `buffer_len` must be valid for writes and `usrptr` must be null
+/// or point to a value of type `H::UserData`.
+#[doc(hidden)]
+pub unsafe extern "C" fn token_handler_open<H: TokenHandler>(
+    cd: *mut libcryptsetup_rs_sys::crypt_device,
+    token: c_int,
+    buffer: *mut *mut c_char,
+    buffer_len: *mut size_t,
+    usrptr: *mut c_void,
+) -> c_int {
+    token_handler_rc(panic::catch_unwind(AssertUnwindSafe(|| {
+        // The device is owned by the caller of libcryptsetup and must not be freed here.
+        let mut device = ManuallyDrop::new(CryptDevice::from_ptr(cd));
+        let usrdata = unsafe { usrptr.cast::<H::UserData>().as_mut() };
+        token_handler_buffer(
+            H::open(&mut device, token as c_uint, usrdata),
+            buffer,
+            buffer_len,
+        )
+    })))
+}
+
+/// # Safety
+///
+/// `pin` must be null or valid for reads of `pin_size` bytes, `buffer` and
+/// `buffer_len` must be valid for writes and `usrptr` must be null or point to
+/// a value of type `H::UserData`.
+#[doc(hidden)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn token_handler_open_pin<H: TokenHandler>(
+    cd: *mut libcryptsetup_rs_sys::crypt_device,
+    token: c_int,
+    pin: *const c_char,
+    pin_size: size_t,
+    buffer: *mut *mut c_char,
+    buffer_len: *mut size_t,
+    usrptr: *mut c_void,
+) -> c_int {
+    token_handler_rc(panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut device = ManuallyDrop::new(CryptDevice::from_ptr(cd));
+        let usrdata = unsafe { usrptr.cast::<H::UserData>().as_mut() };
+        let pin = match ptr_to_option!(pin) {
+            Some(p) => unsafe { slice::from_raw_parts(p.cast::<u8>(), pin_size) },
+            None => &[],
+        };
+        token_handler_buffer(
+            H::open_pin(&mut device, token as c_uint, pin, usrdata),
+            buffer,
+            buffer_len,
+        )
+    })))
+}
+
+/// # Safety
+///
+/// `buffer` must be null or a buffer of `buffer_len` bytes returned by one of
+/// the open trampolines.
+#[doc(hidden)]
+pub unsafe extern "C" fn token_handler_buffer_free(buffer: *mut c_void, buffer_len: size_t) {
+    if buffer.is_null() {
+        return;
+    }
+    let mut passphrase = unsafe {
+        Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.cast::<u8>(),
+            buffer_len,
+        ))
+    };
+    for byte in passphrase.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+/// # Safety
+///
+/// `json` must be null or a NUL terminated string.
+#[doc(hidden)]
+pub unsafe extern "C" fn token_handler_validate<H: TokenHandler>(
+    cd: *mut libcryptsetup_rs_sys::crypt_device,
+    json: *const c_char,
+) -> c_int {
+    token_handler_rc(panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut device = ManuallyDrop::new(CryptDevice::from_ptr(cd));
+        H::validate(&mut device, &token_handler_json(json)?)
+    })))
+}
+
+/// # Safety
+///
+/// `json` must be null or a NUL terminated string.
+#[doc(hidden)]
+pub unsafe extern "C" fn token_handler_dump<H: TokenHandler>(
+    cd: *mut libcryptsetup_rs_sys::crypt_device,
+    json: *const c_char,
+) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut device = ManuallyDrop::new(CryptDevice::from_ptr(cd));
+        if let Ok(json) = token_handler_json(json) {
+            H::dump(&mut device, &json);
+        }
+    }));
+}
+
 /// Register token handler
+///
+/// The callbacks can be generated with the `c_token_handler_*` macros.
+/// [`register_handler`] registers a `TokenHandler` without raw callbacks.
 pub fn register(
     name: &'static str,
     open: libcryptsetup_rs_sys::crypt_token_open_func,
@@ -318,14 +676,151 @@ pub fn register(
     if name.get(name.len() - 1..) != Some("\0") {
         return Err(LibcryptErr::NoNull(name));
     }
-    let handler = libcryptsetup_rs_sys::crypt_token_handler {
+    let handler = Box::new(libcryptsetup_rs_sys::crypt_token_handler {
         name: name.as_ptr().cast::<c_char>(),
         open,
         buffer_free,
         validate,
         dump,
-    };
+    });
     errno!(mutex!(libcryptsetup_rs_sys::crypt_token_register(
-        &handler as *const libcryptsetup_rs_sys::crypt_token_handler,
-    )))
+        &*handler as *const libcryptsetup_rs_sys::crypt_token_handler,
+    )))?;
+    // libcryptsetup keeps a reference to the function table.
+    Box::leak(handler);
+    Ok(())
+}
+
+/// Register a token handler.
+///
+/// libcryptsetup keeps the handler for the lifetime of the process so the
+/// handler name and function table are never freed.
+pub fn register_handler<H: TokenHandler>() -> Result<(), LibcryptErr> {
+    let name = to_cstring!(H::NAME)?;
+    let handler = Box::new(libcryptsetup_rs_sys::crypt_token_handler {
+        name: name.as_ptr(),
+        open: Some(token_handler_open::<H>),
+        buffer_free: Some(token_handler_buffer_free),
+        validate: Some(token_handler_validate::<H>),
+        dump: Some(token_handler_dump::<H>),
+    });
+    errno!(mutex!(libcryptsetup_rs_sys::crypt_token_register(
+        &*handler as *const libcryptsetup_rs_sys::crypt_token_handler,
+    )))?;
+    // Only leak the handler once libcryptsetup holds a reference to it.
+    let _ = name.into_raw();
+    Box::leak(handler);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TestHandler;
+
+    impl TokenHandler for TestHandler {
+        const NAME: &'static str = "test-token";
+
+        type UserData = u32;
+
+        fn open(
+            _: &mut CryptDevice,
+            token: c_uint,
+            usrdata: Option<&mut u32>,
+        ) -> Result<Box<[u8]>, LibcryptErr> {
+            match (token, usrdata) {
+                (0, Some(u)) => {
+                    *u += 1;
+                    Ok(Box::from(&b"passphrase"[..]))
+                }
+                (1, _) => Err(LibcryptErr::NoKey(Vec::new())),
+                _ => panic!("unexpected token"),
+            }
+        }
+
+        fn validate(_: &mut CryptDevice, json: &serde_json::Value) -> Result<(), LibcryptErr> {
+            if json["type"] == Self::NAME {
+                Ok(())
+            } else {
+                Err(LibcryptErr::InvalidConversion)
+            }
+        }
+    }
+
+    #[test]
+    fn test_token_handler_open() {
+        let mut buffer: *mut c_char = ptr::null_mut();
+        let mut buffer_len: size_t = 0;
+        let mut usrdata = 0u32;
+        let rc = unsafe {
+            token_handler_open::<TestHandler>(
+                ptr::null_mut(),
+                0,
+                &mut buffer as *mut _,
+                &mut buffer_len as *mut _,
+                (&mut usrdata as *mut u32).cast::<c_void>(),
+            )
+        };
+        assert_eq!(rc, 0);
+        assert_eq!(usrdata, 1);
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(buffer.cast::<u8>(), buffer_len) },
+            b"passphrase"
+        );
+        unsafe { token_handler_buffer_free(buffer.cast::<c_void>(), buffer_len) };
+    }
+
+    #[test]
+    fn test_token_handler_open_errors() {
+        let mut buffer: *mut c_char = ptr::null_mut();
+        let mut buffer_len: size_t = 0;
+        let rc = unsafe {
+            token_handler_open::<TestHandler>(
+                ptr::null_mut(),
+                1,
+                &mut buffer as *mut _,
+                &mut buffer_len as *mut _,
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(rc, -libc::EPERM);
+        assert!(buffer.is_null());
+
+        let rc = unsafe {
+            token_handler_open::<TestHandler>(
+                ptr::null_mut(),
+                2,
+                &mut buffer as *mut _,
+                &mut buffer_len as *mut _,
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(rc, -libc::EINVAL);
+        assert!(buffer.is_null());
+    }
+
+    #[test]
+    #[cfg(cryptsetup27supported)]
+    fn test_set_token_external_path_validation() {
+        assert!(set_token_external_path(Some(Path::new("relative/tokens"))).is_err());
+        assert!(set_token_external_path(Some(Path::new("/dev/null"))).is_err());
+    }
+
+    #[test]
+    fn test_token_handler_validate() {
+        let valid = c"{\"type\": \"test-token\", \"keyslots\": []}";
+        let rc = unsafe { token_handler_validate::<TestHandler>(ptr::null_mut(), valid.as_ptr()) };
+        assert_eq!(rc, 0);
+
+        let invalid = c"{\"type\": \"other\", \"keyslots\": []}";
+        let rc =
+            unsafe { token_handler_validate::<TestHandler>(ptr::null_mut(), invalid.as_ptr()) };
+        assert_eq!(rc, -libc::EINVAL);
+
+        let malformed = c"{";
+        let rc =
+            unsafe { token_handler_validate::<TestHandler>(ptr::null_mut(), malformed.as_ptr()) };
+        assert_eq!(rc, -libc::EINVAL);
+    }
 }
@@ -0,0 +1,502 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use libc::c_uint;
+use serde_json::{Map, Value};
+
+use crate::{
+    err::LibcryptErr,
+    luks2::json::{
+        invalid, parse_id_array, put_opt, take_object, take_opt_bool, take_opt_string, take_string,
+        take_u32_array,
+    },
+};
+
+const TYPE: &str = "type";
+const KEYSLOTS: &str = "keyslots";
+
+/// Generic LUKS2 token with the mandatory `type` and `keyslots` fields and
+/// all other fields in `extensions`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2Token {
+    /// Type of the token
+    pub type_: String,
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// Type specific fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+impl Luks2Token {
+    /// Create a token without type specific fields
+    pub fn new(type_: &str, keyslots: Vec<c_uint>) -> Self {
+        Luks2Token {
+            type_: type_.to_string(),
+            keyslots,
+            extensions: Map::new(),
+        }
+    }
+
+    /// Check the token against the LUKS2 token rules
+    pub fn validate(&self) -> Result<(), LibcryptErr> {
+        if self.type_.is_empty() {
+            return Err(invalid("Token type is empty".to_string()));
+        }
+        for key in [TYPE, KEYSLOTS] {
+            if self.extensions.contains_key(key) {
+                return Err(invalid(format!(
+                    "Token extensions must not contain the {key} field"
+                )));
+            }
+        }
+        for (i, keyslot) in self.keyslots.iter().enumerate() {
+            if self.keyslots[..i].contains(keyslot) {
+                return Err(invalid(format!(
+                    "Keyslot {keyslot} is assigned to the token more than once"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert the token into its JSON representation
+    pub fn to_json(&self) -> Result<Value, LibcryptErr> {
+        self.validate()?;
+        let mut map = self.extensions.clone();
+        map.insert(TYPE.to_string(), Value::String(self.type_.clone()));
+        map.insert(
+            KEYSLOTS.to_string(),
+            Value::Array(
+                self.keyslots
+                    .iter()
+                    .map(|k| Value::String(k.to_string()))
+                    .collect(),
+            ),
+        );
+        Ok(Value::Object(map))
+    }
+}
+
+impl TryFrom<&Value> for Luks2Token {
+    type Error = LibcryptErr;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        let mut extensions = v
+            .as_object()
+            .ok_or_else(|| invalid(format!("Token is not a JSON object: {v}")))?
+            .clone();
+        let type_ = take_string(&mut extensions, TYPE)?;
+        let keyslots = match extensions.remove(KEYSLOTS) {
+            Some(v) => parse_id_array(KEYSLOTS, v)?,
+            None => return Err(invalid("Token keyslots field is missing".to_string())),
+        };
+        let token = Luks2Token {
+            type_,
+            keyslots,
+            extensions,
+        };
+        token.validate()?;
+        Ok(token)
+    }
+}
+
+/// Token of type `luks2-keyring` unlocking keyslots with a passphrase stored in
+/// the kernel keyring
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Luks2KeyringToken {
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// Description of the key in the kernel keyring
+    pub key_description: String,
+    /// Unrecognized fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+/// Token of type `systemd-tpm2` written by `systemd-cryptenroll`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemdTpm2Token {
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// Base64 encoded sealed key blob
+    pub tpm2_blob: String,
+    /// PCRs the key is bound to
+    pub tpm2_pcrs: Vec<u32>,
+    /// PCR bank, such as `sha256`
+    pub tpm2_pcr_bank: Option<String>,
+    /// Algorithm of the primary key, such as `ecc`
+    pub tpm2_primary_alg: Option<String>,
+    /// Hex encoded policy hash
+    pub tpm2_policy_hash: String,
+    /// Whether a PIN is required to unseal the key
+    pub tpm2_pin: Option<bool>,
+    /// Unrecognized fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+/// Token of type `systemd-fido2` written by `systemd-cryptenroll`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemdFido2Token {
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// Base64 encoded credential id
+    pub fido2_credential: String,
+    /// Base64 encoded salt
+    pub fido2_salt: String,
+    /// Relying party id
+    pub fido2_rp: Option<String>,
+    /// Whether the authenticator PIN is required
+    pub fido2_client_pin_required: Option<bool>,
+    /// Whether user presence is required
+    pub fido2_up_required: Option<bool>,
+    /// Whether user verification is required
+    pub fido2_uv_required: Option<bool>,
+    /// Unrecognized fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+/// Token of type `systemd-pkcs11` written by `systemd-cryptenroll`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemdPkcs11Token {
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// PKCS#11 URI of the key
+    pub pkcs11_uri: String,
+    /// Base64 encoded encrypted key
+    pub pkcs11_key: String,
+    /// Unrecognized fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+/// Token of type `systemd-recovery` marking a recovery key keyslot written by
+/// `systemd-cryptenroll`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemdRecoveryToken {
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// Unrecognized fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+/// Token of type `clevis` written by `clevis luks bind`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClevisToken {
+    /// Keyslots assigned to the token
+    pub keyslots: Vec<c_uint>,
+    /// JWE holding the encrypted passphrase of the keyslots
+    pub jwe: Map<String, Value>,
+    /// Unrecognized fields of the token
+    pub extensions: Map<String, Value>,
+}
+
+/// LUKS2 token converted to the typed representation of its type
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TypedLuks2Token {
+    /// `luks2-keyring` token
+    Keyring(Luks2KeyringToken),
+    /// `systemd-tpm2` token
+    SystemdTpm2(SystemdTpm2Token),
+    /// `systemd-fido2` token
+    SystemdFido2(SystemdFido2Token),
+    /// `systemd-pkcs11` token
+    SystemdPkcs11(SystemdPkcs11Token),
+    /// `systemd-recovery` token
+    SystemdRecovery(SystemdRecoveryToken),
+    /// `clevis` token
+    Clevis(ClevisToken),
+    /// Token of any other type
+    Other(Luks2Token),
+}
+
+impl TypedLuks2Token {
+    /// Check the token against the LUKS2 token rules
+    pub fn validate(&self) -> Result<(), LibcryptErr> {
+        Luks2Token::try_from(self.clone()).map(|_| ())
+    }
+
+    /// Convert the token into its JSON representation
+    pub fn to_json(&self) -> Result<Value, LibcryptErr> {
+        Luks2Token::try_from(self.clone())?.to_json()
+    }
+}
+
+impl TryFrom<Luks2Token> for TypedLuks2Token {
+    type Error = LibcryptErr;
+
+    fn try_from(token: Luks2Token) -> Result<Self, Self::Error> {
+        token.validate()?;
+        let Luks2Token {
+            type_,
+            keyslots,
+            mut extensions,
+        } = token;
+        let e = &mut extensions;
+        Ok(match type_.as_str() {
+            "luks2-keyring" => TypedLuks2Token::Keyring(Luks2KeyringToken {
+                keyslots,
+                key_description: take_string(e, "key_description")?,
+                extensions,
+            }),
+            "systemd-tpm2" => TypedLuks2Token::SystemdTpm2(SystemdTpm2Token {
+                keyslots,
+                tpm2_blob: take_string(e, "tpm2-blob")?,
+                tpm2_pcrs: take_u32_array(e, "tpm2-pcrs")?,
+                tpm2_pcr_bank: take_opt_string(e, "tpm2-pcr-bank")?,
+                tpm2_primary_alg: take_opt_string(e, "tpm2-primary-alg")?,
+                tpm2_policy_hash: take_string(e, "tpm2-policy-hash")?,
+                tpm2_pin: take_opt_bool(e, "tpm2-pin")?,
+                extensions,
+            }),
+            "systemd-fido2" => TypedLuks2Token::SystemdFido2(SystemdFido2Token {
+                keyslots,
+                fido2_credential: take_string(e, "fido2-credential")?,
+                fido2_salt: take_string(e, "fido2-salt")?,
+                fido2_rp: take_opt_string(e, "fido2-rp")?,
+                fido2_client_pin_required: take_opt_bool(e, "fido2-clientPin-required")?,
+                fido2_up_required: take_opt_bool(e, "fido2-up-required")?,
+                fido2_uv_required: take_opt_bool(e, "fido2-uv-required")?,
+                extensions,
+            }),
+            "systemd-pkcs11" => TypedLuks2Token::SystemdPkcs11(SystemdPkcs11Token {
+                keyslots,
+                pkcs11_uri: take_string(e, "pkcs11-uri")?,
+                pkcs11_key: take_string(e, "pkcs11-key")?,
+                extensions,
+            }),
+            "systemd-recovery" => TypedLuks2Token::SystemdRecovery(SystemdRecoveryToken {
+                keyslots,
+                extensions,
+            }),
+            "clevis" => TypedLuks2Token::Clevis(ClevisToken {
+                keyslots,
+                jwe: take_object(e, "jwe")?,
+                extensions,
+            }),
+            _ => TypedLuks2Token::Other(Luks2Token {
+                type_,
+                keyslots,
+                extensions,
+            }),
+        })
+    }
+}
+
+impl TryFrom<TypedLuks2Token> for Luks2Token {
+    type Error = LibcryptErr;
+
+    fn try_from(token: TypedLuks2Token) -> Result<Self, Self::Error> {
+        let (type_, keyslots, mut extensions, fields) = match token {
+            TypedLuks2Token::Keyring(t) => (
+                "luks2-keyring",
+                t.keyslots,
+                t.extensions,
+                vec![("key_description", Some(Value::from(t.key_description)))],
+            ),
+            TypedLuks2Token::SystemdTpm2(t) => (
+                "systemd-tpm2",
+                t.keyslots,
+                t.extensions,
+                vec![
+                    ("tpm2-blob", Some(Value::from(t.tpm2_blob))),
+                    ("tpm2-pcrs", Some(Value::from(t.tpm2_pcrs))),
+                    ("tpm2-pcr-bank", t.tpm2_pcr_bank.map(Value::from)),
+                    ("tpm2-primary-alg", t.tpm2_primary_alg.map(Value::from)),
+                    ("tpm2-policy-hash", Some(Value::from(t.tpm2_policy_hash))),
+                    ("tpm2-pin", t.tpm2_pin.map(Value::from)),
+                ],
+            ),
+            TypedLuks2Token::SystemdFido2(t) => (
+                "systemd-fido2",
+                t.keyslots,
+                t.extensions,
+                vec![
+                    ("fido2-credential", Some(Value::from(t.fido2_credential))),
+                    ("fido2-salt", Some(Value::from(t.fido2_salt))),
+                    ("fido2-rp", t.fido2_rp.map(Value::from)),
+                    (
+                        "fido2-clientPin-required",
+                        t.fido2_client_pin_required.map(Value::from),
+                    ),
+                    ("fido2-up-required", t.fido2_up_required.map(Value::from)),
+                    ("fido2-uv-required", t.fido2_uv_required.map(Value::from)),
+                ],
+            ),
+            TypedLuks2Token::SystemdPkcs11(t) => (
+                "systemd-pkcs11",
+                t.keyslots,
+                t.extensions,
+                vec![
+                    ("pkcs11-uri", Some(Value::from(t.pkcs11_uri))),
+                    ("pkcs11-key", Some(Value::from(t.pkcs11_key))),
+                ],
+            ),
+            TypedLuks2Token::SystemdRecovery(t) => {
+                ("systemd-recovery", t.keyslots, t.extensions, vec![])
+            }
+            TypedLuks2Token::Clevis(t) => (
+                "clevis",
+                t.keyslots,
+                t.extensions,
+                vec![("jwe", Some(Value::Object(t.jwe)))],
+            ),
+            TypedLuks2Token::Other(t) => {
+                t.validate()?;
+                return Ok(t);
+            }
+        };
+        for (key, value) in fields {
+            if extensions.contains_key(key) {
+                return Err(invalid(format!(
+                    "Token extensions must not contain the {key} field"
+                )));
+            }
+            put_opt(&mut extensions, key, value);
+        }
+        let token = Luks2Token {
+            type_: type_.to_string(),
+            keyslots,
+            extensions,
+        };
+        token.validate()?;
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    fn round_trip(value: Value) -> TypedLuks2Token {
+        let token = Luks2Token::try_from(&value).unwrap();
+        let typed = TypedLuks2Token::try_from(token.clone()).unwrap();
+        assert_eq!(Luks2Token::try_from(typed.clone()).unwrap(), token);
+        assert_eq!(typed.to_json().unwrap(), value);
+        typed
+    }
+
+    #[test]
+    fn test_keyring_round_trip() {
+        let typed = round_trip(json!({
+            "type": "luks2-keyring",
+            "keyslots": ["0", "3"],
+            "key_description": "test-key",
+        }));
+        match typed {
+            TypedLuks2Token::Keyring(t) => {
+                assert_eq!(t.keyslots, vec![0, 3]);
+                assert_eq!(t.key_description, "test-key");
+            }
+            _ => panic!("Expected a luks2-keyring token"),
+        }
+    }
+
+    #[test]
+    fn test_systemd_round_trip() {
+        let typed = round_trip(json!({
+            "type": "systemd-tpm2",
+            "keyslots": ["1"],
+            "tpm2-blob": "AJ4AIA==",
+            "tpm2-pcrs": [7],
+            "tpm2-pcr-bank": "sha256",
+            "tpm2-primary-alg": "ecc",
+            "tpm2-policy-hash": "b1a2",
+            "tpm2-pin": false,
+            "tpm2_srk": "AAAA",
+        }));
+        match typed {
+            TypedLuks2Token::SystemdTpm2(t) => {
+                assert_eq!(t.tpm2_pcrs, vec![7]);
+                assert_eq!(t.tpm2_pin, Some(false));
+                assert!(t.extensions.contains_key("tpm2_srk"));
+            }
+            _ => panic!("Expected a systemd-tpm2 token"),
+        }
+
+        round_trip(json!({
+            "type": "systemd-fido2",
+            "keyslots": ["2"],
+            "fido2-credential": "Y3JlZA==",
+            "fido2-salt": "c2FsdA==",
+            "fido2-rp": "io.systemd.cryptsetup",
+            "fido2-clientPin-required": true,
+            "fido2-up-required": true,
+            "fido2-uv-required": false,
+        }));
+        round_trip(json!({
+            "type": "systemd-pkcs11",
+            "keyslots": ["4"],
+            "pkcs11-uri": "pkcs11:token=test",
+            "pkcs11-key": "a2V5",
+        }));
+        round_trip(json!({
+            "type": "systemd-recovery",
+            "keyslots": ["5"],
+        }));
+    }
+
+    #[test]
+    fn test_clevis_and_other_round_trip() {
+        round_trip(json!({
+            "type": "clevis",
+            "keyslots": ["1"],
+            "jwe": {"ciphertext": "", "protected": "eyJhbGciOiJkaXIifQ"},
+        }));
+        match round_trip(json!({
+            "type": "vendor-token",
+            "keyslots": [],
+            "data": [1, 2, 3],
+        })) {
+            TypedLuks2Token::Other(t) => assert_eq!(t.type_, "vendor-token"),
+            _ => panic!("Expected an unrecognized token"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        for value in [
+            json!([]),
+            json!({"keyslots": []}),
+            json!({"type": 1, "keyslots": []}),
+            json!({"type": "", "keyslots": []}),
+            json!({"type": "test"}),
+            json!({"type": "test", "keyslots": "0"}),
+            json!({"type": "test", "keyslots": [0]}),
+            json!({"type": "test", "keyslots": ["-1"]}),
+            json!({"type": "test", "keyslots": ["1", "1"]}),
+        ] {
+            assert!(Luks2Token::try_from(&value).is_err(), "{value}");
+        }
+
+        // libcryptsetup accepts ids that are not in canonical form.
+        let token = Luks2Token::try_from(&json!({"type": "test", "keyslots": ["01"]})).unwrap();
+        assert_eq!(token.keyslots, vec![1]);
+
+        for value in [
+            json!({"type": "luks2-keyring", "keyslots": []}),
+            json!({"type": "systemd-tpm2", "keyslots": [], "tpm2-blob": "", "tpm2-policy-hash": "", "tpm2-pcrs": [-1]}),
+            json!({"type": "systemd-fido2", "keyslots": [], "fido2-credential": "", "fido2-salt": "", "fido2-up-required": null}),
+            json!({"type": "clevis", "keyslots": [], "jwe": "abc"}),
+        ] {
+            let token = Luks2Token::try_from(&value).unwrap();
+            assert!(TypedLuks2Token::try_from(token).is_err(), "{value}");
+        }
+
+        let mut token = Luks2Token::new("test", vec![0]);
+        token
+            .extensions
+            .insert("keyslots".to_string(), json!(["1"]));
+        assert!(token.to_json().is_err());
+
+        let mut keyring = Luks2KeyringToken {
+            keyslots: vec![],
+            key_description: "key".to_string(),
+            extensions: Map::new(),
+        };
+        keyring
+            .extensions
+            .insert("key_description".to_string(), json!("other"));
+        assert!(TypedLuks2Token::Keyring(keyring).validate().is_err());
+    }
+}
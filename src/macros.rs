@@ -16,6 +16,7 @@ macro_rules! mutex {
             panic!("Enable the mutex feature for this crate to allow calling libcryptsetup methods from multiple threads");
         }
 
+        let _capture = $crate::log::CaptureScope::enter();
         unsafe { $libcryptsetup_call }
     }};
 }
@@ -23,13 +24,12 @@ macro_rules! mutex {
 /// Convert an errno-zero-success return pattern into a `Result<(), LibcryptErr>`
 macro_rules! errno {
     ( $rc:expr ) => {
+        errno!($rc, $crate::err::ErrnoContext::Generic)
+    };
+    ( $rc:expr, $context:expr ) => {
         match $rc {
-            i if i < 0 => {
-                return Err($crate::err::LibcryptErr::IOError(
-                    std::io::Error::from_raw_os_error(-i),
-                ))
-            }
-            i if i > 0 => panic!("Unexpected return value {}", i),
+            i if i < 0 => return Err($crate::err::LibcryptErr::from_errno_in(-i, $context)),
+            i if i > 0 => return Err($crate::err::LibcryptErr::UnexpectedReturn(i)),
             _ => Result::<(), $crate::err::LibcryptErr>::Ok(()),
         }
     };
@@ -39,11 +39,7 @@ macro_rules! errno {
 macro_rules! errno_int_success {
     ( $rc:expr ) => {
         match $rc {
-            i if i < 0 => {
-                return Err($crate::err::LibcryptErr::IOError(
-                    std::io::Error::from_raw_os_error(-i),
-                ))
-            }
+            i if i < 0 => return Err($crate::err::LibcryptErr::from_errno(-i)),
             i => Result::<_, $crate::err::LibcryptErr>::Ok(i),
         }
     };
@@ -357,6 +353,114 @@ macro_rules! c_token_handler_dump {
     };
 }
 
+#[macro_export]
+/// Export the symbols of an external libcryptsetup token plugin for a type
+/// implementing `TokenHandler`.
+///
+/// Use this in a `cdylib` crate and install the library as
+/// `libcryptsetup-token-<NAME>.so` in the libcryptsetup token directory. The
+/// second argument is the version string reported by the plugin.
+///
+/// libcryptsetup looks up the plugin symbols with the `CRYPTSETUP_TOKEN_1.0`
+/// symbol version, so the linker must be given a version script that declares
+/// that version, for example from the build script of the plugin crate:
+///
+/// ```text
+/// CRYPTSETUP_TOKEN_1.0 {
+/// };
+/// ```
+///
+/// ```ignore
+/// println!("cargo:rustc-cdylib-link-arg=-Wl,--version-script=token.map");
+/// ```
+macro_rules! export_token_plugin {
+    ( $handler:ty, $version:literal ) => {
+        /// # Safety
+        ///
+        /// Called by libcryptsetup with valid pointers.
+        #[no_mangle]
+        pub unsafe extern "C" fn cryptsetup_token_open(
+            cd: *mut $crate::__token_plugin::crypt_device,
+            token: $crate::__token_plugin::c_int,
+            buffer: *mut *mut $crate::__token_plugin::c_char,
+            buffer_len: *mut $crate::__token_plugin::size_t,
+            usrptr: *mut $crate::__token_plugin::c_void,
+        ) -> $crate::__token_plugin::c_int {
+            $crate::__token_plugin::token_handler_open::<$handler>(
+                cd, token, buffer, buffer_len, usrptr,
+            )
+        }
+
+        /// # Safety
+        ///
+        /// Called by libcryptsetup with valid pointers.
+        #[no_mangle]
+        #[allow(clippy::too_many_arguments)]
+        pub unsafe extern "C" fn cryptsetup_token_open_pin(
+            cd: *mut $crate::__token_plugin::crypt_device,
+            token: $crate::__token_plugin::c_int,
+            pin: *const $crate::__token_plugin::c_char,
+            pin_size: $crate::__token_plugin::size_t,
+            buffer: *mut *mut $crate::__token_plugin::c_char,
+            buffer_len: *mut $crate::__token_plugin::size_t,
+            usrptr: *mut $crate::__token_plugin::c_void,
+        ) -> $crate::__token_plugin::c_int {
+            $crate::__token_plugin::token_handler_open_pin::<$handler>(
+                cd, token, pin, pin_size, buffer, buffer_len, usrptr,
+            )
+        }
+
+        /// # Safety
+        ///
+        /// Called by libcryptsetup with a buffer returned by an open function.
+        #[no_mangle]
+        pub unsafe extern "C" fn cryptsetup_token_buffer_free(
+            buffer: *mut $crate::__token_plugin::c_void,
+            buffer_len: $crate::__token_plugin::size_t,
+        ) {
+            $crate::__token_plugin::token_handler_buffer_free(buffer, buffer_len)
+        }
+
+        /// # Safety
+        ///
+        /// Called by libcryptsetup with valid pointers.
+        #[no_mangle]
+        pub unsafe extern "C" fn cryptsetup_token_validate(
+            cd: *mut $crate::__token_plugin::crypt_device,
+            json: *const $crate::__token_plugin::c_char,
+        ) -> $crate::__token_plugin::c_int {
+            $crate::__token_plugin::token_handler_validate::<$handler>(cd, json)
+        }
+
+        /// # Safety
+        ///
+        /// Called by libcryptsetup with valid pointers.
+        #[no_mangle]
+        pub unsafe extern "C" fn cryptsetup_token_dump(
+            cd: *mut $crate::__token_plugin::crypt_device,
+            json: *const $crate::__token_plugin::c_char,
+        ) {
+            $crate::__token_plugin::token_handler_dump::<$handler>(cd, json)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn cryptsetup_token_version() -> *const $crate::__token_plugin::c_char {
+            concat!($version, "\0")
+                .as_ptr()
+                .cast::<$crate::__token_plugin::c_char>()
+        }
+
+        std::arch::global_asm!(
+            ".symver cryptsetup_token_open, cryptsetup_token_open@@CRYPTSETUP_TOKEN_1.0",
+            ".symver cryptsetup_token_open_pin, cryptsetup_token_open_pin@@CRYPTSETUP_TOKEN_1.0",
+            ".symver cryptsetup_token_buffer_free, cryptsetup_token_buffer_free@@CRYPTSETUP_TOKEN_1.0",
+            ".symver cryptsetup_token_validate, cryptsetup_token_validate@@CRYPTSETUP_TOKEN_1.0",
+            ".symver cryptsetup_token_dump, cryptsetup_token_dump@@CRYPTSETUP_TOKEN_1.0",
+            ".symver cryptsetup_token_version, cryptsetup_token_version@@CRYPTSETUP_TOKEN_1.0",
+        );
+    };
+}
+
 #[cfg(test)]
 mod test {
     use crate::consts::vals::CryptLogLevel;
@@ -2,7 +2,21 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::{consts::flags::CryptActivate, device::CryptDevice, err::LibcryptErr};
+use std::{
+    ffi::CString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    consts::{flags::CryptActivate, vals::EncryptionFormat},
+    device::{CryptDevice, CryptInit},
+    err::{ErrnoContext, LibcryptErr},
+};
+
+/// Prefix of the device-mapper UUID of every mapping created by libcryptsetup
+const DM_UUID_PREFIX: &str = "CRYPT-";
+const SYS_BLOCK: &str = "/sys/block";
 
 /// Record containing data on the given active device
 pub struct ActiveDevice {
@@ -49,11 +63,14 @@ impl<'a> CryptRuntimeHandle<'a> {
             flags: 0,
         };
         let name_cstring = to_cstring!(self.name)?;
-        errno!(mutex!(libcryptsetup_rs_sys::crypt_get_active_device(
-            self.reference.as_ptr(),
-            name_cstring.as_ptr(),
-            &mut cad as *mut _,
-        )))
+        errno!(
+            mutex!(libcryptsetup_rs_sys::crypt_get_active_device(
+                self.reference.as_ptr(),
+                name_cstring.as_ptr(),
+                &mut cad as *mut _,
+            )),
+            ErrnoContext::MappingLookup
+        )
         .and_then(|_| ActiveDevice::try_from(&cad))
     }
 
@@ -68,3 +85,158 @@ impl<'a> CryptRuntimeHandle<'a> {
         ))
     }
 }
+
+/// Active device-mapper mapping created by libcryptsetup
+pub struct ActiveMapping {
+    /// Name of the mapping in `/dev/mapper`
+    pub name: String,
+    /// Format of the mapping taken from its device-mapper UUID
+    pub format: EncryptionFormat,
+    /// Device-mapper UUID, for example `CRYPT-LUKS2-<uuid>-<name>`
+    pub dm_uuid: String,
+    /// Backing data device
+    pub device_path: PathBuf,
+    /// Attributes of the active device
+    pub active_device: ActiveDevice,
+}
+
+impl ActiveMapping {
+    /// Initialize a device for this mapping by name
+    pub fn init(&self) -> Result<CryptDevice, LibcryptErr> {
+        CryptInit::init_by_name_and_header(&self.name, None)
+    }
+}
+
+/// Parse the format from a device-mapper UUID of the form
+/// `CRYPT-<TYPE>-<...>`. Internal helper mappings such as `CRYPT-SUBDEV-`
+/// and `CRYPT-TEMP-` do not correspond to a format and yield `None`.
+fn dm_uuid_format(dm_uuid: &str) -> Option<EncryptionFormat> {
+    let type_ = dm_uuid.strip_prefix(DM_UUID_PREFIX)?.split('-').next()?;
+    let type_cstring = CString::new(type_).ok()?;
+    EncryptionFormat::from_ptr(type_cstring.as_ptr()).ok()
+}
+
+fn read_sysfs_attr(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path).map(|s| s.trim_end().to_string())
+}
+
+/// List name, format and device-mapper UUID of libcryptsetup managed
+/// mappings found under a sysfs block directory
+fn dm_crypt_mappings(
+    sys_block: &Path,
+) -> Result<Vec<(String, EncryptionFormat, String)>, LibcryptErr> {
+    let mut mappings = Vec::new();
+    for entry in fs::read_dir(sys_block).map_err(LibcryptErr::IOError)? {
+        let dm_dir = entry.map_err(LibcryptErr::IOError)?.path().join("dm");
+        let (name, dm_uuid) = match (
+            read_sysfs_attr(&dm_dir.join("name")),
+            read_sysfs_attr(&dm_dir.join("uuid")),
+        ) {
+            (Ok(name), Ok(dm_uuid)) => (name, dm_uuid),
+            // Not a device-mapper device or removed while listing
+            _ => continue,
+        };
+        if let Some(format) = dm_uuid_format(&dm_uuid) {
+            mappings.push((name, format, dm_uuid));
+        }
+    }
+    mappings.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(mappings)
+}
+
+/// Get the backing device path and runtime attributes of a mapping
+fn active_mapping_status(name: &str) -> Result<(PathBuf, ActiveDevice), LibcryptErr> {
+    let mut device = CryptInit::init_by_name_and_header(name, None)?;
+    let device_path = device.status_handle().get_device_path()?.to_owned();
+    let active_device = device.runtime_handle(name).get_active_device()?;
+    Ok((device_path, active_device))
+}
+
+/// List all active mappings created by libcryptsetup, identified by the
+/// `CRYPT-` prefix of their device-mapper UUID.
+///
+/// Mappings that are removed while listing, or that cannot be opened, for
+/// example because their backing device is missing, are skipped so that
+/// they do not hide the other mappings. Only a failure to list the mappings
+/// themselves is returned as an error.
+pub fn active_mappings() -> Result<Vec<ActiveMapping>, LibcryptErr> {
+    let mut mappings = Vec::new();
+    for (name, format, dm_uuid) in dm_crypt_mappings(Path::new(SYS_BLOCK))? {
+        let (device_path, active_device) = match active_mapping_status(&name) {
+            Ok(status) => status,
+            Err(_) => continue,
+        };
+        mappings.push(ActiveMapping {
+            name,
+            format,
+            dm_uuid,
+            device_path,
+            active_device,
+        });
+    }
+    Ok(mappings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_dm_uuid_format() {
+        assert_eq!(
+            dm_uuid_format("CRYPT-LUKS2-0d1c2e3f4a5b6c7d8e9f0a1b2c3d4e5f-test"),
+            Some(EncryptionFormat::Luks2)
+        );
+        assert_eq!(
+            dm_uuid_format("CRYPT-VERITY-0d1c2e3f4a5b6c7d8e9f0a1b2c3d4e5f-test"),
+            Some(EncryptionFormat::Verity)
+        );
+        assert_eq!(
+            dm_uuid_format("CRYPT-PLAIN-test"),
+            Some(EncryptionFormat::Plain)
+        );
+        assert_eq!(dm_uuid_format("CRYPT-SUBDEV-0d1c2e3f-test_dif"), None);
+        assert_eq!(dm_uuid_format("LVM-abcdef"), None);
+        assert_eq!(dm_uuid_format(""), None);
+    }
+
+    #[test]
+    fn test_dm_crypt_mappings() {
+        let sys_block =
+            env::temp_dir().join(format!("libcryptsetup-rs-sys-block-{}", std::process::id()));
+        for (dev, attrs) in [
+            ("dm-0", Some(("luks", "CRYPT-LUKS2-0d1c2e3f-luks"))),
+            ("dm-1", Some(("vg-lv", "LVM-abcdef"))),
+            ("dm-2", Some(("hash", "CRYPT-VERITY-0d1c2e3f-hash"))),
+            ("dm-3", Some(("luks_dif", "CRYPT-SUBDEV-0d1c2e3f-luks_dif"))),
+            ("sda", None),
+        ] {
+            let dm_dir = sys_block.join(dev).join("dm");
+            fs::create_dir_all(&dm_dir).unwrap();
+            if let Some((name, uuid)) = attrs {
+                fs::write(dm_dir.join("name"), format!("{name}\n")).unwrap();
+                fs::write(dm_dir.join("uuid"), format!("{uuid}\n")).unwrap();
+            }
+        }
+
+        let mappings = dm_crypt_mappings(&sys_block).unwrap();
+        fs::remove_dir_all(&sys_block).unwrap();
+        assert_eq!(
+            mappings,
+            vec![
+                (
+                    "hash".to_string(),
+                    EncryptionFormat::Verity,
+                    "CRYPT-VERITY-0d1c2e3f-hash".to_string()
+                ),
+                (
+                    "luks".to_string(),
+                    EncryptionFormat::Luks2,
+                    "CRYPT-LUKS2-0d1c2e3f-luks".to_string()
+                ),
+            ]
+        );
+    }
+}
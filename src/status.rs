@@ -13,6 +13,8 @@ use crate::{
     format::{CryptParamsIntegrity, CryptParamsVerity},
 };
 
+#[cfg(cryptsetup24supported)]
+use crate::luks2::metadata::Luks2Metadata;
 #[cfg(cryptsetup24supported)]
 use serde_json::Value;
 use uuid::Uuid;
@@ -58,6 +60,12 @@ impl<'a> CryptDeviceStatusHandle<'a> {
         Ok(json)
     }
 
+    /// Dump the LUKS2 metadata of the device as typed structures
+    #[cfg(cryptsetup24supported)]
+    pub fn luks2_metadata(&mut self) -> Result<Luks2Metadata, LibcryptErr> {
+        Luks2Metadata::try_from(&self.dump_json()?)
+    }
+
     /// Get cipher used by device
     pub fn get_cipher(&mut self) -> Result<String, LibcryptErr> {
         from_str_ptr_to_owned!(libcryptsetup_rs_sys::crypt_get_cipher(
@@ -144,7 +152,17 @@ impl<'a> CryptDeviceStatusHandle<'a> {
     }
 
     /// Get Integrity device parameters
+    ///
+    /// libcryptsetup does not expose the journal keys of a device so the key
+    /// fields of the returned parameters are always empty.
     pub fn get_integrity_info(&mut self) -> Result<CryptParamsIntegrity, LibcryptErr> {
+        self.get_raw_integrity_info()
+            .and_then(|integrity| CryptParamsIntegrity::try_from(&integrity))
+    }
+
+    pub(crate) fn get_raw_integrity_info(
+        &mut self,
+    ) -> Result<libcryptsetup_rs_sys::crypt_params_integrity, LibcryptErr> {
         let mut integrity = libcryptsetup_rs_sys::crypt_params_integrity {
             journal_size: 0,
             journal_watermark: 0,
@@ -166,7 +184,7 @@ impl<'a> CryptDeviceStatusHandle<'a> {
             self.reference.as_ptr(),
             &mut integrity as *mut _,
         )))
-        .and_then(|_| CryptParamsIntegrity::try_from(&integrity))
+        .map(|_| integrity)
     }
 }
 
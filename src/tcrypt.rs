@@ -0,0 +1,245 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs::File,
+    io::{Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    consts::{
+        flags::{CryptActivate, CryptTcrypt},
+        vals::EncryptionFormat,
+    },
+    device::CryptDevice,
+    err::LibcryptErr,
+    format::{CryptParamsTcrypt, CryptParamsTcryptRef},
+};
+
+const TCRYPT_HDR_SIZE: usize = 512;
+const TCRYPT_HDR_SYSTEM_OFFSET: u64 = 31744;
+const TCRYPT_HDR_HIDDEN_OFFSET: u64 = 65536;
+const TCRYPT_HDR_HIDDEN_OFFSET_BCK: u64 = 65536;
+const TCRYPT_HDR_OFFSET_BCK: u64 = 131072;
+
+/// Location of a TrueCrypt or VeraCrypt header
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TcryptHeader {
+    /// Header of the outer volume at the start of the device
+    Normal,
+    /// Header of the hidden volume
+    Hidden,
+    /// Backup header of the outer volume at the end of the device
+    Backup,
+    /// Backup header of the hidden volume at the end of the device
+    HiddenBackup,
+    /// Header of a system encrypted disk
+    System,
+}
+
+impl TcryptHeader {
+    fn flags(self) -> CryptTcrypt {
+        match self {
+            TcryptHeader::Normal => CryptTcrypt::empty(),
+            TcryptHeader::Hidden => CryptTcrypt::HIDDEN_HEADER,
+            TcryptHeader::Backup => CryptTcrypt::BACKUP_HEADER,
+            TcryptHeader::HiddenBackup => CryptTcrypt::HIDDEN_HEADER | CryptTcrypt::BACKUP_HEADER,
+            TcryptHeader::System => CryptTcrypt::SYSTEM_HEADER,
+        }
+    }
+
+    fn offset(self, device_size: u64) -> Option<u64> {
+        match self {
+            TcryptHeader::Normal => Some(0),
+            TcryptHeader::Hidden => Some(TCRYPT_HDR_HIDDEN_OFFSET),
+            TcryptHeader::Backup => device_size.checked_sub(TCRYPT_HDR_OFFSET_BCK),
+            TcryptHeader::HiddenBackup => device_size.checked_sub(TCRYPT_HDR_HIDDEN_OFFSET_BCK),
+            TcryptHeader::System => Some(TCRYPT_HDR_SYSTEM_OFFSET),
+        }
+    }
+}
+
+/// Error returned when opening a TrueCrypt or VeraCrypt volume
+#[derive(Debug)]
+pub enum TcryptError {
+    /// A header is present at one of the requested locations but none could
+    /// be decrypted with the passphrase, keyfiles and PIM
+    WrongPassphrase,
+    /// None of the requested header locations contain header data
+    NoHeader,
+    /// Any other error reported by libcryptsetup
+    Crypt(LibcryptErr),
+}
+
+impl Display for TcryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TcryptError::WrongPassphrase => {
+                write!(
+                    f,
+                    "No TCRYPT header could be decrypted with the given passphrase"
+                )
+            }
+            TcryptError::NoHeader => write!(f, "No TCRYPT header found on the device"),
+            TcryptError::Crypt(ref e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for TcryptError {}
+
+impl From<LibcryptErr> for TcryptError {
+    fn from(e: LibcryptErr) -> Self {
+        TcryptError::Crypt(e)
+    }
+}
+
+/// Builder to load and activate TrueCrypt and VeraCrypt volumes
+pub struct TcryptOpen<'a> {
+    passphrase: Option<&'a [u8]>,
+    keyfiles: Vec<PathBuf>,
+    veracrypt_pim: u32,
+    flags: CryptTcrypt,
+    headers: Vec<TcryptHeader>,
+}
+
+impl Default for TcryptOpen<'_> {
+    fn default() -> Self {
+        TcryptOpen::new()
+    }
+}
+
+impl<'a> TcryptOpen<'a> {
+    /// Create a builder that tries the normal header with TrueCrypt modes
+    pub fn new() -> Self {
+        TcryptOpen {
+            passphrase: None,
+            keyfiles: Vec::new(),
+            veracrypt_pim: 0,
+            flags: CryptTcrypt::empty(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Passphrase of the volume
+    pub fn passphrase(mut self, passphrase: &'a [u8]) -> Self {
+        self.passphrase = Some(passphrase);
+        self
+    }
+
+    /// Add a keyfile; keyfiles are applied in the order they are added
+    pub fn keyfile(mut self, keyfile: &Path) -> Self {
+        self.keyfiles.push(keyfile.to_owned());
+        self
+    }
+
+    /// Also try VeraCrypt key derivation
+    pub fn veracrypt(mut self, veracrypt: bool) -> Self {
+        self.flags.set(CryptTcrypt::VERA_MODES, veracrypt);
+        self
+    }
+
+    /// VeraCrypt personal iterations multiplier; implies `veracrypt(true)`
+    pub fn veracrypt_pim(mut self, pim: u32) -> Self {
+        self.veracrypt_pim = pim;
+        self.veracrypt(true)
+    }
+
+    /// Also try legacy cipher modes of old TrueCrypt versions
+    pub fn legacy_modes(mut self, legacy_modes: bool) -> Self {
+        self.flags.set(CryptTcrypt::LEGACY_MODES, legacy_modes);
+        self
+    }
+
+    /// Add a header location to try. Locations are tried in the order they
+    /// are added; if none are added only the normal header is tried.
+    pub fn header(mut self, header: TcryptHeader) -> Self {
+        self.headers.push(header);
+        self
+    }
+
+    /// Load the first header that can be decrypted and return its location.
+    pub fn load(&self, device: &mut CryptDevice) -> Result<TcryptHeader, TcryptError> {
+        let headers = if self.headers.is_empty() {
+            &[TcryptHeader::Normal][..]
+        } else {
+            &self.headers[..]
+        };
+        for header in headers {
+            let params = CryptParamsTcrypt {
+                passphrase: self.passphrase.map(|p| p.to_vec()),
+                keyfiles: if self.keyfiles.is_empty() {
+                    None
+                } else {
+                    Some(self.keyfiles.clone())
+                },
+                hash_name: String::new(),
+                cipher: String::new(),
+                mode: String::new(),
+                key_size: 0,
+                flags: CryptTcrypt::from_bits_retain(self.flags.bits()) | header.flags(),
+                veracrypt_pim: self.veracrypt_pim,
+            };
+            let mut params_ref: CryptParamsTcryptRef<'_> = (&params).try_into()?;
+            match device
+                .context_handle()
+                .load(Some(EncryptionFormat::Tcrypt), Some(&mut params_ref))
+            {
+                Ok(()) => return Ok(*header),
+                Err(LibcryptErr::NoKey(_)) => (),
+                Err(e) => return Err(TcryptError::Crypt(e)),
+            }
+        }
+
+        let device_path = device.status_handle().get_device_path()?.to_owned();
+        if headers
+            .iter()
+            .any(|header| header_present(&device_path, *header))
+        {
+            Err(TcryptError::WrongPassphrase)
+        } else {
+            Err(TcryptError::NoHeader)
+        }
+    }
+
+    /// Load the first header that can be decrypted, activate the volume and
+    /// return the location of the header that was used.
+    pub fn activate(
+        &self,
+        device: &mut CryptDevice,
+        name: &str,
+        flags: CryptActivate,
+    ) -> Result<TcryptHeader, TcryptError> {
+        let header = self.load(device)?;
+        device
+            .activate_handle()
+            .activate_by_volume_key(Some(name), None, flags)?;
+        Ok(header)
+    }
+}
+
+/// Check whether the header location contains data that could be a header.
+///
+/// Encrypted headers are indistinguishable from random data so a header is
+/// considered present unless the location is outside of the device or is
+/// filled with a single repeated byte, as in a wiped or sparse device.
+fn header_present(device_path: &Path, header: TcryptHeader) -> bool {
+    let mut buf = [0u8; TCRYPT_HDR_SIZE];
+    File::open(device_path)
+        .and_then(|mut f| {
+            let device_size = f.seek(SeekFrom::End(0))?;
+            match header.offset(device_size) {
+                Some(offset) if offset + TCRYPT_HDR_SIZE as u64 <= device_size => {
+                    f.read_exact_at(&mut buf, offset)?;
+                    Ok(buf.iter().any(|b| *b != buf[0]))
+                }
+                _ => Ok(false),
+            }
+        })
+        .unwrap_or(false)
+}
@@ -0,0 +1,202 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{Cursor, Write},
+    path::PathBuf,
+};
+
+use super::loopback;
+
+use crate::{
+    consts::{
+        flags::{CryptActivate, CryptVolumeKey},
+        vals::{EncryptionFormat, KeyslotInfo},
+    },
+    device::CryptInit,
+    Either,
+};
+
+pub fn test_header_backup_restore_memory() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            dev.keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+
+            let mut backup_path = PathBuf::from(file_path);
+            backup_path.set_extension("hdr");
+            dev.backup_handle()
+                .header_backup(Some(EncryptionFormat::Luks2), &backup_path)
+                .unwrap();
+            let file_backup = fs::read(&backup_path).unwrap();
+            fs::remove_file(&backup_path).unwrap();
+
+            let backup = dev
+                .backup_handle()
+                .header_backup_to_memory(Some(EncryptionFormat::Luks2))
+                .unwrap();
+            assert_eq!(backup.as_ref(), file_backup.as_slice());
+
+            let mut written = Vec::new();
+            dev.backup_handle()
+                .header_backup_to_writer(None, &mut written)
+                .unwrap();
+            assert_eq!(written, file_backup);
+            drop(dev);
+
+            let mut f = OpenOptions::new().write(true).open(dev_path).unwrap();
+            f.write_all(&[0u8; 4096]).unwrap();
+            f.sync_all().unwrap();
+            drop(f);
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.backup_handle()
+                .header_restore_from_slice(Some(EncryptionFormat::Luks2), backup.as_ref())
+                .unwrap();
+            dev.context_handle()
+                .load::<()>(Some(EncryptionFormat::Luks2), None)
+                .unwrap();
+            dev.backup_handle()
+                .header_restore_from_reader(None, &mut Cursor::new(written))
+                .unwrap();
+            dev.activate_handle()
+                .activate_by_passphrase(None, None, b"thisisatest", CryptActivate::empty())
+                .unwrap();
+
+            let mut garbage = Cursor::new(vec![0u8; 4096]);
+            assert!(dev
+                .backup_handle()
+                .header_restore_from_reader(None, &mut garbage)
+                .is_err());
+        },
+    )
+}
+
+pub fn test_header_restore_checked() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            let keyslot = dev
+                .keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+
+            let mut backup_path = PathBuf::from(file_path);
+            backup_path.set_extension("hdr");
+            dev.backup_handle()
+                .header_backup(Some(EncryptionFormat::Luks2), &backup_path)
+                .unwrap();
+
+            let new_keyslot = dev
+                .keyslot_handle()
+                .add_by_passphrase(None, b"thisisatest", b"anotherpassphrase")
+                .unwrap();
+            let token = dev
+                .token_handle()
+                .luks2_keyring_set(None, "test-key")
+                .unwrap();
+
+            let mut seen = None;
+            assert!(!dev
+                .backup_handle()
+                .header_restore_checked(None, &backup_path, false, |diff| {
+                    seen = Some(diff.clone());
+                    false
+                })
+                .unwrap());
+            let diff = seen.unwrap();
+            assert!(diff.device_header_loaded);
+            assert_eq!(diff.keyslots_removed, vec![new_keyslot]);
+            assert_eq!(diff.tokens_removed, vec![token]);
+            assert!(diff.keyslots_added.is_empty() && diff.keyslots_changed.is_empty());
+            assert_eq!(
+                dev.keyslot_handle().status(new_keyslot).unwrap(),
+                KeyslotInfo::Active
+            );
+
+            assert!(dev
+                .backup_handle()
+                .header_restore_checked(None, &backup_path, false, |_| true)
+                .unwrap());
+            assert_eq!(
+                dev.keyslot_handle().status(new_keyslot).unwrap(),
+                KeyslotInfo::Inactive
+            );
+            assert_eq!(
+                dev.keyslot_handle().status(keyslot).unwrap(),
+                KeyslotInfo::ActiveLast
+            );
+
+            // A freshly formatted device has a different UUID.
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            assert!(dev
+                .backup_handle()
+                .header_restore_checked(None, &backup_path, false, |_| true)
+                .is_err());
+
+            // Without any header on the device the backup cannot be checked.
+            OpenOptions::new()
+                .write(true)
+                .open(dev_path)
+                .and_then(|mut f| f.write_all(&[0u8; 4 * 1024 * 1024]))
+                .unwrap();
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            assert!(dev
+                .backup_handle()
+                .header_restore_checked(None, &backup_path, false, |_| true)
+                .is_err());
+            let mut seen = None;
+            assert!(dev
+                .backup_handle()
+                .header_restore_checked(None, &backup_path, true, |diff| {
+                    seen = Some(diff.clone());
+                    true
+                })
+                .unwrap());
+            assert!(!seen.unwrap().device_header_loaded);
+            dev.context_handle().load::<()>(None, None).unwrap();
+            assert_eq!(
+                dev.keyslot_handle().status(keyslot).unwrap(),
+                KeyslotInfo::ActiveLast
+            );
+
+            fs::remove_file(&backup_path).unwrap();
+        },
+    )
+}
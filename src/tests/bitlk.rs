@@ -0,0 +1,81 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use uuid::Uuid;
+
+use crate::{consts::vals::EncryptionFormat, device::CryptInit, tests::loopback};
+
+const FIXTURE_SIZE: usize = 256 * 1024;
+
+fn fixture_path(name: &str) -> PathBuf {
+    [
+        env!("CARGO_MANIFEST_DIR"),
+        "src",
+        "tests",
+        "fixtures",
+        "bitlk",
+        name,
+    ]
+    .iter()
+    .collect()
+}
+
+fn write_fixture(dev_path: &Path, name: &str) {
+    let contents = fs::read(fixture_path(name)).unwrap();
+    assert_eq!(contents.len(), FIXTURE_SIZE);
+    let mut f = OpenOptions::new().write(true).open(dev_path).unwrap();
+    f.write_all(&contents).unwrap();
+    f.sync_all().unwrap();
+}
+
+pub fn test_bitlk_load() {
+    loopback::use_loopback(
+        FIXTURE_SIZE,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            write_fixture(dev_path, "bitlk.img");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .load::<()>(Some(EncryptionFormat::Bitlk), None)
+                .unwrap();
+            assert_eq!(
+                dev.format_handle().get_type().unwrap(),
+                EncryptionFormat::Bitlk
+            );
+            assert_eq!(
+                dev.status_handle().get_uuid().unwrap(),
+                Uuid::from_str("6a0c3ad4-7b4f-4c1e-9a53-3d2b8f1e0c11").unwrap()
+            );
+            assert_eq!(dev.status_handle().get_cipher().unwrap(), "aes");
+            assert_eq!(
+                dev.status_handle().get_cipher_mode().unwrap(),
+                "xts-plain64"
+            );
+        },
+    )
+}
+
+pub fn test_bitlk_no_header() {
+    loopback::use_loopback(
+        FIXTURE_SIZE,
+        true,
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            assert!(dev
+                .context_handle()
+                .load::<()>(Some(EncryptionFormat::Bitlk), None)
+                .is_err());
+        },
+    )
+}
@@ -3,21 +3,26 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
+    cell::RefCell,
     ffi::CString,
     fs::{File, OpenOptions},
     io::{self, Read, Write},
     mem::MaybeUninit,
+    ops::ControlFlow,
     path::{Path, PathBuf},
-    ptr, slice,
+    ptr,
+    rc::Rc,
+    slice,
 };
 
 use crate::{
     consts::{
-        flags::{CryptActivate, CryptDeactivate, CryptKeyfile, CryptVolumeKey},
-        vals::EncryptionFormat,
+        flags::{CryptActivate, CryptDeactivate, CryptKeyfile, CryptVolumeKey, CryptWipe},
+        vals::{CryptLogLevel, CryptWipePattern, EncryptionFormat},
     },
     device::CryptInit,
     err::LibcryptErr,
+    runtime::active_mappings,
     tests::loopback,
     Either,
 };
@@ -313,3 +318,120 @@ pub fn test_unencrypted() {
         },
     )
 }
+
+pub fn test_active_mappings() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let device_name = "test-active-mappings";
+            let passphrase = "abadpassphrase";
+
+            let keyslot = init(dev_path, passphrase);
+            activate_by_passphrase(dev_path, device_name, keyslot, passphrase);
+
+            let mappings = active_mappings().unwrap();
+            let mapping = mappings
+                .iter()
+                .find(|m| m.name == device_name)
+                .expect("Active mapping not listed");
+            assert_eq!(mapping.format, EncryptionFormat::Luks2);
+            assert!(mapping.dm_uuid.starts_with("CRYPT-LUKS2-"));
+            assert_eq!(mapping.device_path, dev_path);
+            assert!(mapping.active_device.size > 0);
+
+            let mut dev = mapping.init().unwrap();
+            assert_eq!(
+                dev.runtime_handle(device_name)
+                    .get_active_device()
+                    .unwrap()
+                    .offset,
+                mapping.active_device.offset
+            );
+            dev.activate_handle()
+                .deactivate(device_name, CryptDeactivate::empty())
+                .unwrap();
+            assert!(!active_mappings()
+                .unwrap()
+                .iter()
+                .any(|m| m.name == device_name));
+        },
+    )
+}
+
+pub fn test_wrong_passphrase_error() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            init(dev_path, "abadpassphrase");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .load::<()>(Some(EncryptionFormat::Luks2), None)
+                .unwrap();
+            let err = dev
+                .activate_handle()
+                .activate_by_passphrase(None, None, b"wrongpassphrase", CryptActivate::empty())
+                .unwrap_err();
+            assert!(matches!(err, LibcryptErr::NoKey(_)));
+            assert_eq!(err.errno(), Some(libc::EPERM));
+            assert!(err
+                .messages()
+                .iter()
+                .any(|m| m.contains("No key available")));
+
+            let err = dev.keyslot_handle().destroy(7).unwrap_err();
+            assert_eq!(err.errno(), Some(libc::ENOENT));
+        },
+    )
+}
+
+pub fn test_closure_callbacks() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            init(dev_path, "abadpassphrase");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .load::<()>(Some(EncryptionFormat::Luks2), None)
+                .unwrap();
+
+            let logged = Rc::new(RefCell::new(Vec::new()));
+            let logged_clone = Rc::clone(&logged);
+            dev.set_log_fn(move |level, msg| {
+                logged_clone.borrow_mut().push((level, msg.to_string()))
+            });
+            dev.activate_handle()
+                .activate_by_passphrase(None, None, b"wrongpassphrase", CryptActivate::empty())
+                .unwrap_err();
+            assert!(logged
+                .borrow()
+                .iter()
+                .any(|(level, msg)| *level == CryptLogLevel::Error
+                    && msg.contains("No key available")));
+
+            let mut calls = 0;
+            dev.wipe_handle()
+                .wipe_with_progress(
+                    dev_path,
+                    CryptWipePattern::Zero,
+                    0,
+                    4 * 1024 * 1024,
+                    1024 * 1024,
+                    CryptWipe::empty(),
+                    |_, _| {
+                        calls += 1;
+                        ControlFlow::Break(())
+                    },
+                )
+                .unwrap_err();
+            assert_eq!(calls, 1);
+        },
+    )
+}
@@ -0,0 +1,114 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+};
+
+use super::loopback;
+
+use crate::{
+    consts::{
+        flags::{CryptActivate, CryptDeactivate, CryptVolumeKey},
+        vals::EncryptionFormat,
+    },
+    device::{CryptInit, CryptInitBuilder},
+    Either,
+};
+
+pub fn test_init_builder_detached_header() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let mut header_path = PathBuf::from(file_path);
+            header_path.set_extension("header");
+            File::create(&header_path)
+                .unwrap()
+                .set_len(16 * 1024 * 1024)
+                .unwrap();
+
+            let mut dev = CryptInitBuilder::new()
+                .header_device(&header_path)
+                .data_device(dev_path)
+                .data_offset(0)
+                .build()
+                .unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            dev.keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+            assert!(dev.header_is_detached().unwrap());
+            assert_eq!(dev.status_handle().get_data_offset(), 0);
+            drop(dev);
+
+            assert!(CryptInitBuilder::new()
+                .header_device(&header_path)
+                .data_device(dev_path)
+                .expected_format(EncryptionFormat::Luks1)
+                .build()
+                .is_err());
+            let mut dev = CryptInitBuilder::new()
+                .header_device(&header_path)
+                .data_device(dev_path)
+                .expected_format(EncryptionFormat::Luks2)
+                .build()
+                .unwrap();
+            assert!(dev.header_is_detached().unwrap());
+
+            let name = "test-init-builder";
+            dev.activate_handle()
+                .activate_by_passphrase(Some(name), None, b"thisisatest", CryptActivate::empty())
+                .unwrap();
+            drop(dev);
+
+            let mut dev = CryptInitBuilder::new()
+                .name(name)
+                .header_device(&header_path)
+                .expected_format(EncryptionFormat::Luks2)
+                .build()
+                .unwrap();
+            assert!(dev.header_is_detached().unwrap());
+            assert!(CryptInitBuilder::new()
+                .name(name)
+                .header_device(&header_path)
+                .expected_format(EncryptionFormat::Luks1)
+                .build()
+                .is_err());
+            dev.activate_handle()
+                .deactivate(name, CryptDeactivate::empty())
+                .unwrap();
+
+            assert!(CryptInitBuilder::new()
+                .name(name)
+                .data_device(dev_path)
+                .build()
+                .is_err());
+            assert!(CryptInitBuilder::new().build().is_err());
+            fs::remove_file(&header_path).unwrap();
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            assert!(!dev.header_is_detached().unwrap());
+        },
+    )
+}
@@ -0,0 +1,151 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::{
+    consts::flags::{CryptActivate, CryptDeactivate},
+    device::CryptInit,
+    format::CryptParamsIntegrity,
+    integrity::CryptIntegrityMode,
+    tests::loopback,
+};
+
+const JOURNAL_INTEGRITY_KEY: &[u8] = &[0x11; 32];
+const JOURNAL_CRYPT_KEY: &[u8] = &[0x22; 32];
+
+fn integrity_params(journal_keys: bool) -> CryptParamsIntegrity {
+    CryptParamsIntegrity {
+        journal_size: 0,
+        journal_watermark: 0,
+        journal_commit_time: 0,
+        interleave_sectors: 0,
+        tag_size: 4,
+        sector_size: 512,
+        buffer_sectors: 0,
+        integrity: "crc32c".to_string(),
+        integrity_key_size: 0,
+        journal_integrity: "hmac(sha256)".to_string(),
+        journal_integrity_key: if journal_keys {
+            JOURNAL_INTEGRITY_KEY.to_vec()
+        } else {
+            Vec::new()
+        },
+        journal_crypt: "ctr(aes)".to_string(),
+        journal_crypt_key: if journal_keys {
+            JOURNAL_CRYPT_KEY.to_vec()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+pub fn test_integrity_journal_keys() {
+    loopback::use_loopback(
+        64 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.integrity_handle()
+                .format(&integrity_params(true), None)
+                .unwrap();
+            dev.integrity_handle()
+                .activate(
+                    "test-integrity-device",
+                    None,
+                    CryptIntegrityMode::Journal,
+                    CryptActivate::empty(),
+                )
+                .unwrap();
+
+            let report = dev
+                .integrity_handle()
+                .report("test-integrity-device")
+                .unwrap();
+            assert_eq!(report.params.integrity, "crc32c");
+            assert_eq!(report.params.journal_integrity, "hmac(sha256)");
+            assert_eq!(report.params.journal_crypt, "ctr(aes)");
+            assert!(report.params.journal_crypt_key.is_empty());
+            assert_eq!(
+                report.journal_integrity_key_size as usize,
+                JOURNAL_INTEGRITY_KEY.len()
+            );
+            assert_eq!(
+                report.journal_crypt_key_size as usize,
+                JOURNAL_CRYPT_KEY.len()
+            );
+            assert_eq!(report.mode, CryptIntegrityMode::Journal);
+            assert_eq!(report.integrity_failures, 0);
+            dev.activate_handle()
+                .deactivate("test-integrity-device", CryptDeactivate::empty())
+                .unwrap();
+
+            // The journal keys are not stored on disk and must be provided again.
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.integrity_handle()
+                .load(Some(&integrity_params(false)))
+                .unwrap();
+            assert!(dev
+                .integrity_handle()
+                .activate(
+                    "test-integrity-device",
+                    None,
+                    CryptIntegrityMode::Journal,
+                    CryptActivate::empty(),
+                )
+                .is_err());
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.integrity_handle()
+                .load(Some(&integrity_params(true)))
+                .unwrap();
+            dev.integrity_handle()
+                .activate(
+                    "test-integrity-device",
+                    None,
+                    CryptIntegrityMode::Journal,
+                    CryptActivate::empty(),
+                )
+                .unwrap();
+            dev.activate_handle()
+                .deactivate("test-integrity-device", CryptDeactivate::empty())
+                .unwrap();
+        },
+    )
+}
+
+pub fn test_integrity_bitmap_and_direct_modes() {
+    loopback::use_loopback(
+        64 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut params = integrity_params(false);
+            params.journal_integrity = String::new();
+            params.journal_crypt = String::new();
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.integrity_handle().format(&params, None).unwrap();
+
+            for (mode, flags) in [
+                (CryptIntegrityMode::Direct, CryptActivate::empty()),
+                (CryptIntegrityMode::Bitmap, CryptActivate::RECALCULATE),
+            ] {
+                let mut dev = CryptInit::init(dev_path).unwrap();
+                dev.integrity_handle().load(Some(&params)).unwrap();
+                dev.integrity_handle()
+                    .activate("test-integrity-device", None, mode, flags)
+                    .unwrap();
+                let report = dev
+                    .integrity_handle()
+                    .report("test-integrity-device")
+                    .unwrap();
+                assert_eq!(report.mode, mode);
+                assert_eq!(report.journal_crypt_key_size, 0);
+                dev.activate_handle()
+                    .deactivate("test-integrity-device", CryptDeactivate::empty())
+                    .unwrap();
+            }
+        },
+    )
+}
@@ -0,0 +1,119 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fs::File, io::Write, path::PathBuf};
+
+use crate::{
+    consts::{
+        flags::{CryptActivate, CryptVolumeKey},
+        vals::EncryptionFormat,
+    },
+    device::CryptInit,
+    keyslot_context::CryptKeyslotContext,
+    tests::loopback,
+    Either,
+};
+
+pub fn test_add_keyslot_by_keyfile_context() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+
+            dev.keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+
+            let keyfile_path = PathBuf::from(format!("{}-key", file_path.display()));
+            File::create(&keyfile_path)
+                .unwrap()
+                .write_all(b"thisisakeyfile")
+                .unwrap();
+
+            let keyfile_keyslot = {
+                let mut existing =
+                    CryptKeyslotContext::init_by_passphrase(&mut dev, b"thisisatest").unwrap();
+                let mut new =
+                    CryptKeyslotContext::init_by_keyfile(&mut dev, &keyfile_path, None, 0).unwrap();
+                dev.keyslot_handle()
+                    .add_by_keyslot_context(
+                        None,
+                        &mut existing,
+                        None,
+                        &mut new,
+                        CryptVolumeKey::empty(),
+                    )
+                    .unwrap()
+            };
+
+            // Enroll a passphrase using only the key file as the existing credential.
+            let passphrase_keyslot = {
+                let mut existing =
+                    CryptKeyslotContext::init_by_keyfile(&mut dev, &keyfile_path, None, 0).unwrap();
+                let mut new =
+                    CryptKeyslotContext::init_by_passphrase(&mut dev, b"anotherpassphrase")
+                        .unwrap();
+                dev.keyslot_handle()
+                    .add_by_keyslot_context(
+                        Some(keyfile_keyslot),
+                        &mut existing,
+                        None,
+                        &mut new,
+                        CryptVolumeKey::empty(),
+                    )
+                    .unwrap()
+            };
+            assert_ne!(keyfile_keyslot, passphrase_keyslot);
+
+            let mut from_keyfile = [0u8; 64];
+            let mut kc =
+                CryptKeyslotContext::init_by_keyfile(&mut dev, &keyfile_path, None, 0).unwrap();
+            dev.volume_key_handle()
+                .get_by_keyslot_context(None, &mut from_keyfile, &mut kc)
+                .unwrap();
+
+            let mut from_passphrase = [0u8; 64];
+            let mut kc =
+                CryptKeyslotContext::init_by_passphrase(&mut dev, b"anotherpassphrase").unwrap();
+            dev.volume_key_handle()
+                .get_by_keyslot_context(Some(passphrase_keyslot), &mut from_passphrase, &mut kc)
+                .unwrap();
+            assert_eq!(from_keyfile, from_passphrase);
+
+            let mut kc =
+                CryptKeyslotContext::init_by_keyfile(&mut dev, &keyfile_path, None, 0).unwrap();
+            let keyslot = dev
+                .activate_handle()
+                .activate_by_keyslot_context(
+                    None,
+                    None,
+                    &mut kc,
+                    None,
+                    None,
+                    CryptActivate::empty(),
+                )
+                .unwrap();
+            assert_eq!(keyslot, keyfile_keyslot);
+
+            let mut kc = CryptKeyslotContext::init_by_passphrase(&mut dev, b"wrong").unwrap();
+            assert!(dev
+                .volume_key_handle()
+                .get_by_keyslot_context(None, &mut from_passphrase, &mut kc)
+                .is_err());
+
+            std::fs::remove_file(&keyfile_path).unwrap();
+        },
+    )
+}
@@ -0,0 +1,56 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use super::loopback;
+
+use crate::{
+    consts::{flags::CryptVolumeKey, vals::EncryptionFormat},
+    device::CryptInit,
+    luks1::Luks1Header,
+    Either,
+};
+
+pub fn test_luks1_header() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks1,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            let keyslot = dev
+                .keyslot_handle()
+                .add_by_key(Some(2), None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+
+            let hdr = Luks1Header::from_file(dev_path).unwrap();
+            let mut status = dev.status_handle();
+            assert_eq!(hdr.cipher, status.get_cipher().unwrap());
+            assert_eq!(hdr.cipher_mode, status.get_cipher_mode().unwrap());
+            assert_eq!(hdr.uuid, status.get_uuid().unwrap().to_string());
+            assert_eq!(u64::from(hdr.payload_offset), status.get_data_offset());
+            assert_eq!(hdr.key_bytes as i32, status.get_volume_key_size());
+
+            let mut keyslots = dev.keyslot_handle();
+            for i in 0..8 {
+                assert_eq!(hdr.keyslot_status(i), keyslots.status(i).unwrap());
+            }
+            assert_eq!(hdr.active_keyslots().collect::<Vec<_>>(), vec![keyslot]);
+            let pbkdf = keyslots.get_pbkdf(keyslot).unwrap();
+            assert_eq!(hdr.keyslots[2].iterations, pbkdf.iterations);
+            assert_eq!(Some(hdr.hash.as_str()), pbkdf.hash.as_deref());
+            let (offset, _) = keyslots.area(keyslot).unwrap();
+            assert_eq!(u64::from(hdr.keyslots[2].key_material_offset) * 512, offset);
+            assert_eq!(hdr.keyslots[2].stripes, 4000);
+        },
+    )
+}
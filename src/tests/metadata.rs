@@ -0,0 +1,125 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    consts::{flags::CryptVolumeKey, vals::EncryptionFormat},
+    device::CryptInit,
+    luks2::{
+        header::{Luks2HeaderState, Luks2OnDiskHeader},
+        metadata::Luks2Metadata,
+    },
+    tests::loopback,
+    Either,
+};
+
+pub fn test_luks2_metadata() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            let first = dev
+                .keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+            let second = dev
+                .keyslot_handle()
+                .add_by_passphrase(None, b"thisisatest", b"anotherpassphrase")
+                .unwrap();
+            let token = dev
+                .token_handle()
+                .luks2_keyring_set(None, "test-key")
+                .unwrap();
+            dev.token_handle()
+                .assign_keyslot(token, Some(second))
+                .unwrap();
+
+            let metadata = dev.status_handle().luks2_metadata().unwrap();
+            assert_eq!(
+                metadata.keyslots.keys().copied().collect::<Vec<_>>(),
+                vec![first, second]
+            );
+            assert_eq!(metadata.segments.len(), 1);
+            assert_eq!(metadata.segments[&0].size, None);
+            let (_, digest) = metadata.segment_digest(0).unwrap();
+            assert_eq!(digest.segments, vec![0]);
+            let mut keyslots = metadata.segment_keyslots(0);
+            keyslots.sort_unstable();
+            assert_eq!(keyslots, vec![first, second]);
+            assert_eq!(metadata.keyslot_tokens(second), vec![token]);
+            assert!(metadata.keyslot_tokens(first).is_empty());
+            assert!(!metadata.is_reencryption_in_progress());
+
+            let backup_path = PathBuf::from(format!("{}-backup", file_path.display()));
+            dev.backup_handle()
+                .header_backup(Some(EncryptionFormat::Luks2), &backup_path)
+                .unwrap();
+            assert_eq!(
+                Luks2Metadata::from_header_file(&backup_path).unwrap(),
+                metadata
+            );
+            assert_eq!(Luks2Metadata::from_header_file(dev_path).unwrap(), metadata);
+            fs::remove_file(&backup_path).unwrap();
+        },
+    )
+}
+
+pub fn test_luks2_on_disk_header() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain64"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            dev.keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+            let metadata = dev.status_handle().luks2_metadata().unwrap();
+            drop(dev);
+
+            let header = Luks2OnDiskHeader::from_file(dev_path).unwrap();
+            assert_eq!(header.state(), Luks2HeaderState::InSync);
+            assert_eq!(header.metadata().unwrap(), metadata);
+            let primary = header.primary.as_ref().unwrap();
+            let secondary = header.secondary.as_ref().unwrap();
+            assert_eq!(primary.header.seqid, secondary.header.seqid);
+            assert_eq!(secondary.offset, primary.header.hdr_size);
+
+            // Damage the JSON area of the primary copy.
+            let mut f = OpenOptions::new().write(true).open(dev_path).unwrap();
+            f.seek(SeekFrom::Start(4096)).unwrap();
+            f.write_all(b"garbage").unwrap();
+            f.sync_all().unwrap();
+
+            let header = Luks2OnDiskHeader::from_file(dev_path).unwrap();
+            assert_eq!(header.state(), Luks2HeaderState::PrimaryCorrupt);
+            assert_eq!(header.metadata().unwrap(), metadata);
+        },
+    )
+}
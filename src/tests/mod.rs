@@ -4,11 +4,30 @@
 
 use std::env::var;
 
+#[cfg(cryptsetup23supported)]
+pub mod backup;
+#[cfg(cryptsetup23supported)]
+pub mod bitlk;
 pub mod encrypt;
+#[cfg(cryptsetup24supported)]
+pub mod init;
+pub mod integrity;
 pub mod keyfile;
+#[cfg(cryptsetup26supported)]
+pub mod keyslot_context;
 pub mod loopback;
+pub mod luks1;
+#[cfg(cryptsetup24supported)]
+pub mod metadata;
 #[cfg(cryptsetup24supported)]
 pub mod reencrypt;
+pub mod tcrypt;
+#[cfg(cryptsetup24supported)]
+pub mod token;
+#[cfg(cryptsetup27supported)]
+pub mod token_plugin;
+#[cfg(cryptsetup23supported)]
+pub mod verity;
 
 fn format_with_zeros() -> bool {
     var("FORMAT_WITH_ZEROS")
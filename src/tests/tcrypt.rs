@@ -0,0 +1,186 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    consts::flags::{CryptActivate, CryptDeactivate},
+    device::CryptInit,
+    tcrypt::{TcryptError, TcryptHeader, TcryptOpen},
+    tests::loopback,
+};
+
+const FIXTURE_SIZE: usize = 384 * 1024;
+
+fn fixture_path(name: &str) -> PathBuf {
+    [
+        env!("CARGO_MANIFEST_DIR"),
+        "src",
+        "tests",
+        "fixtures",
+        "tcrypt",
+        name,
+    ]
+    .iter()
+    .collect()
+}
+
+fn write_fixture(dev_path: &Path, name: &str) {
+    let contents = fs::read(fixture_path(name)).unwrap();
+    assert_eq!(contents.len(), FIXTURE_SIZE);
+    let mut f = OpenOptions::new().write(true).open(dev_path).unwrap();
+    f.write_all(&contents).unwrap();
+    f.sync_all().unwrap();
+}
+
+pub fn test_tcrypt_load_headers() {
+    loopback::use_loopback(
+        FIXTURE_SIZE,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            write_fixture(dev_path, "truecrypt-hidden.tc");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            let header = TcryptOpen::new()
+                .passphrase(b"outerpassphrase")
+                .load(&mut dev)
+                .unwrap();
+            assert_eq!(header, TcryptHeader::Normal);
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            let header = TcryptOpen::new()
+                .passphrase(b"outerpassphrase")
+                .header(TcryptHeader::Backup)
+                .load(&mut dev)
+                .unwrap();
+            assert_eq!(header, TcryptHeader::Backup);
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            let header = TcryptOpen::new()
+                .passphrase(b"hiddenpassphrase")
+                .header(TcryptHeader::Normal)
+                .header(TcryptHeader::Hidden)
+                .load(&mut dev)
+                .unwrap();
+            assert_eq!(header, TcryptHeader::Hidden);
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            let header = TcryptOpen::new()
+                .passphrase(b"hiddenpassphrase")
+                .header(TcryptHeader::HiddenBackup)
+                .load(&mut dev)
+                .unwrap();
+            assert_eq!(header, TcryptHeader::HiddenBackup);
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            assert!(matches!(
+                TcryptOpen::new()
+                    .passphrase(b"wrongpassphrase")
+                    .header(TcryptHeader::Normal)
+                    .header(TcryptHeader::Hidden)
+                    .load(&mut dev),
+                Err(TcryptError::WrongPassphrase)
+            ));
+        },
+    )
+}
+
+pub fn test_tcrypt_load_keyfile_and_pim() {
+    loopback::use_loopback(
+        FIXTURE_SIZE,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            write_fixture(dev_path, "truecrypt-keyfile.tc");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            assert!(matches!(
+                TcryptOpen::new()
+                    .passphrase(b"keyfilepassphrase")
+                    .load(&mut dev),
+                Err(TcryptError::WrongPassphrase)
+            ));
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            TcryptOpen::new()
+                .passphrase(b"keyfilepassphrase")
+                .keyfile(&fixture_path("truecrypt.key"))
+                .load(&mut dev)
+                .unwrap();
+
+            write_fixture(dev_path, "veracrypt-pim.tc");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            assert!(matches!(
+                TcryptOpen::new()
+                    .passphrase(b"verapassphrase")
+                    .veracrypt(true)
+                    .load(&mut dev),
+                Err(TcryptError::WrongPassphrase)
+            ));
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            TcryptOpen::new()
+                .passphrase(b"verapassphrase")
+                .veracrypt_pim(1)
+                .load(&mut dev)
+                .unwrap();
+        },
+    )
+}
+
+pub fn test_tcrypt_no_header() {
+    loopback::use_loopback(
+        FIXTURE_SIZE,
+        true,
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            assert!(matches!(
+                TcryptOpen::new()
+                    .passphrase(b"outerpassphrase")
+                    .header(TcryptHeader::Normal)
+                    .header(TcryptHeader::Hidden)
+                    .header(TcryptHeader::Backup)
+                    .load(&mut dev),
+                Err(TcryptError::NoHeader)
+            ));
+        },
+    )
+}
+
+pub fn test_tcrypt_activate() {
+    loopback::use_loopback(
+        FIXTURE_SIZE,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            write_fixture(dev_path, "truecrypt-hidden.tc");
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            let header = TcryptOpen::new()
+                .passphrase(b"hiddenpassphrase")
+                .header(TcryptHeader::Hidden)
+                .activate(&mut dev, "test-tcrypt", CryptActivate::READONLY)
+                .unwrap();
+            assert_eq!(header, TcryptHeader::Hidden);
+
+            let size = dev
+                .runtime_handle("test-tcrypt")
+                .get_active_device()
+                .unwrap()
+                .size;
+            assert_eq!(size, 64 * 1024 / 512);
+
+            dev.activate_handle()
+                .deactivate("test-tcrypt", CryptDeactivate::empty())
+                .unwrap();
+        },
+    )
+}
@@ -0,0 +1,87 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::{
+    consts::{flags::CryptVolumeKey, vals::EncryptionFormat},
+    device::CryptInit,
+    luks2::{token::CryptTokenInfo, token_types::Luks2Token},
+    tests::loopback,
+    Either,
+};
+
+pub fn test_token_iter() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            let first = dev
+                .keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+            let second = dev
+                .keyslot_handle()
+                .add_by_passphrase(None, b"thisisatest", b"anotherpassphrase")
+                .unwrap();
+
+            let max = dev.token_handle().max().unwrap();
+            assert!(max > 2);
+            assert_eq!(dev.token_handle().iter().unwrap().count(), 0);
+            assert_eq!(
+                dev.token_handle().free_slots().unwrap(),
+                (0..max).collect::<Vec<_>>()
+            );
+
+            let keyring_token = dev
+                .token_handle()
+                .luks2_keyring_set(Some(2), "test-key")
+                .unwrap();
+            dev.token_handle()
+                .assign_keyslot(keyring_token, Some(first))
+                .unwrap();
+            let external_token = dev
+                .token_handle()
+                .set(None, &Luks2Token::new("test-external", vec![first, second]))
+                .unwrap();
+
+            let entries = dev
+                .token_handle()
+                .iter()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(entries.len(), 2);
+            let keyring_entry = entries.iter().find(|e| e.id == keyring_token).unwrap();
+            assert!(matches!(
+                keyring_entry.info,
+                CryptTokenInfo::Internal(ref t) if t == "luks2-keyring"
+            ));
+            assert_eq!(keyring_entry.keyslots, vec![first]);
+            assert_eq!(keyring_entry.json["key_description"], "test-key");
+            let external_entry = entries.iter().find(|e| e.id == external_token).unwrap();
+            assert!(matches!(
+                external_entry.info,
+                CryptTokenInfo::ExternalUnknown(ref t) if t == "test-external"
+            ));
+            let mut keyslots = external_entry.keyslots.clone();
+            keyslots.sort_unstable();
+            assert_eq!(keyslots, vec![first, second]);
+
+            let free = dev.token_handle().free_slots().unwrap();
+            assert_eq!(free.len(), max as usize - 2);
+            assert!(!free.contains(&keyring_token));
+            assert!(!free.contains(&external_token));
+        },
+    )
+}
@@ -0,0 +1,104 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::json;
+
+use crate::{
+    consts::{
+        flags::{CryptActivate, CryptVolumeKey},
+        vals::EncryptionFormat,
+    },
+    device::CryptInit,
+    luks2::token::{get_token_external_path, set_token_external_path, CryptTokenInfo, TokenInput},
+    tests::loopback,
+    Either,
+};
+
+fn build_plugin() -> PathBuf {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = manifest_dir.join("target").join("token-plugin");
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .arg("build")
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("examples/token-plugin/Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    target_dir.join("debug/libcryptsetup_token_rust_example.so")
+}
+
+pub fn test_external_token_plugin() {
+    loopback::use_loopback(
+        50 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let plugin_dir = PathBuf::from(format!("{}-tokens", file_path.display()));
+            fs::create_dir_all(&plugin_dir).unwrap();
+            fs::copy(
+                build_plugin(),
+                plugin_dir.join("libcryptsetup-token-rust-example.so"),
+            )
+            .unwrap();
+            set_token_external_path(Some(&plugin_dir)).unwrap();
+            assert_eq!(get_token_external_path().unwrap(), Some(plugin_dir.clone()));
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.context_handle()
+                .format::<()>(
+                    EncryptionFormat::Luks2,
+                    ("aes", "xts-plain"),
+                    None,
+                    Either::Right(512 / 8),
+                    None,
+                )
+                .unwrap();
+            let keyslot = dev
+                .keyslot_handle()
+                .add_by_key(None, None, b"thisisatest", CryptVolumeKey::empty())
+                .unwrap();
+
+            // The plugin rejects tokens without a passphrase.
+            assert!(dev
+                .token_handle()
+                .json_set(TokenInput::AddToken(&json!({
+                    "type": "rust-example",
+                    "keyslots": [],
+                })))
+                .is_err());
+
+            let token = dev
+                .token_handle()
+                .json_set(TokenInput::AddToken(&json!({
+                    "type": "rust-example",
+                    "keyslots": [],
+                    "passphrase": "thisisatest",
+                })))
+                .unwrap();
+            dev.token_handle()
+                .assign_keyslot(token, Some(keyslot))
+                .unwrap();
+            assert!(matches!(
+                dev.token_handle().status(token).unwrap(),
+                CryptTokenInfo::External(ref t) if t == "rust-example"
+            ));
+
+            let opened = dev
+                .token_handle()
+                .activate_by_token::<()>(None, Some(token), None, CryptActivate::empty())
+                .unwrap();
+            assert_eq!(opened, keyslot);
+
+            fs::remove_dir_all(&plugin_dir).unwrap();
+        },
+    )
+}
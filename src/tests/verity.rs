@@ -0,0 +1,259 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{remove_file, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    consts::{
+        flags::{CryptActivateVerity, CryptDeactivate, CryptVerity},
+        vals::{CryptStatusInfo, EncryptionFormat},
+    },
+    device::{CryptDevice, CryptInit},
+    format::{CryptParamsVerity, CryptParamsVerityRef},
+    status::status,
+    tests::loopback,
+    verity::DEFAULT_VERITY_SALT_SIZE,
+    verity_tree::VerityHashTree,
+    Either,
+};
+
+use rand::random;
+use uuid::Uuid;
+
+const BLOCK_SIZE: u32 = 4096;
+const DATA_BLOCKS: u64 = 2048;
+
+fn format_verity(dev_path: &Path) -> (CryptDevice, Vec<u8>) {
+    let mut dev = CryptInit::init(dev_path).unwrap();
+    let params = CryptParamsVerity {
+        hash_name: "sha256".to_string(),
+        data_device: dev_path.to_owned(),
+        hash_device: None,
+        fec_device: None,
+        salt: random::<[u8; 32]>().to_vec(),
+        hash_type: 1,
+        data_block_size: BLOCK_SIZE,
+        hash_block_size: BLOCK_SIZE,
+        data_size: DATA_BLOCKS,
+        hash_area_offset: DATA_BLOCKS * u64::from(BLOCK_SIZE),
+        fec_area_offset: 0,
+        fec_roots: 0,
+        flags: CryptVerity::CREATE_HASH,
+    };
+    let mut params_ref: CryptParamsVerityRef<'_> = (&params).try_into().unwrap();
+    dev.context_handle()
+        .format(
+            EncryptionFormat::Verity,
+            ("", ""),
+            None,
+            Either::Right(0),
+            Some(&mut params_ref),
+        )
+        .unwrap();
+
+    let mut root_hash = vec![0u8; 32];
+    let (_, size) = dev
+        .volume_key_handle()
+        .get(None, &mut root_hash, None)
+        .unwrap();
+    root_hash.truncate(size);
+    (dev, root_hash)
+}
+
+pub fn test_verity_activate_without_signature() {
+    loopback::use_loopback(
+        16 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let (mut dev, mut root_hash) = format_verity(dev_path);
+
+            dev.activate_handle()
+                .activate_by_signed_key(
+                    Some("test-verity-device"),
+                    &root_hash,
+                    None,
+                    CryptActivateVerity::READONLY,
+                )
+                .unwrap();
+            assert_eq!(
+                status(Some(&mut dev), "test-verity-device").unwrap(),
+                CryptStatusInfo::Active
+            );
+            dev.activate_handle()
+                .deactivate("test-verity-device", CryptDeactivate::empty())
+                .unwrap();
+
+            root_hash[0] ^= 0xff;
+            assert!(dev
+                .activate_handle()
+                .activate_by_signed_key(None, &root_hash, None, CryptActivateVerity::READONLY)
+                .is_err());
+        },
+    )
+}
+
+pub fn test_verity_format_verify_activate() {
+    loopback::use_loopback(
+        16 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, _file_path| {
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            let params = CryptParamsVerity {
+                hash_name: "sha256".to_string(),
+                data_device: dev_path.to_owned(),
+                hash_device: None,
+                fec_device: None,
+                salt: Vec::new(),
+                hash_type: 1,
+                data_block_size: BLOCK_SIZE,
+                hash_block_size: BLOCK_SIZE,
+                data_size: DATA_BLOCKS,
+                hash_area_offset: DATA_BLOCKS * u64::from(BLOCK_SIZE),
+                fec_area_offset: 0,
+                fec_roots: 0,
+                flags: CryptVerity::empty(),
+            };
+            let result = dev.verity_handle().format(&params).unwrap();
+            assert_eq!(result.salt.len(), DEFAULT_VERITY_SALT_SIZE as usize);
+            assert_eq!(result.root_hash.len(), 32);
+            assert_eq!(dev.verity_handle().root_hash().unwrap(), result.root_hash);
+
+            dev.verity_handle().verify(&result.root_hash).unwrap();
+            let mut wrong_root_hash = result.root_hash.clone();
+            wrong_root_hash[0] ^= 0xff;
+            assert!(dev.verity_handle().verify(&wrong_root_hash).is_err());
+
+            let mut dev = CryptInit::init(dev_path).unwrap();
+            dev.verity_handle().load(&params).unwrap();
+            assert_eq!(
+                dev.status_handle().get_verity_info().unwrap().salt,
+                result.salt
+            );
+            dev.verity_handle()
+                .activate(
+                    "test-verity-device",
+                    &result.root_hash,
+                    CryptActivateVerity::empty(),
+                )
+                .unwrap();
+            assert_eq!(
+                status(Some(&mut dev), "test-verity-device").unwrap(),
+                CryptStatusInfo::Active
+            );
+            dev.activate_handle()
+                .deactivate("test-verity-device", CryptDeactivate::empty())
+                .unwrap();
+
+            let mut data = OpenOptions::new().write(true).open(dev_path).unwrap();
+            data.seek(SeekFrom::Start(u64::from(BLOCK_SIZE))).unwrap();
+            data.write_all(&random::<[u8; 32]>()).unwrap();
+            data.sync_all().unwrap();
+            assert!(dev.verity_handle().verify(&result.root_hash).is_err());
+        },
+    )
+}
+
+fn read_range(path: &Path, offset: u64, len: u64) -> Vec<u8> {
+    let mut file = File::open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    let mut buf = vec![0u8; len as usize];
+    file.read_exact(&mut buf).unwrap();
+    buf
+}
+
+pub fn test_verity_hash_tree_matches_libcryptsetup() {
+    loopback::use_loopback(
+        16 * 1024 * 1024,
+        super::format_with_zeros(),
+        super::do_cleanup(),
+        |dev_path, file_path| {
+            let data_area_size = DATA_BLOCKS * u64::from(BLOCK_SIZE);
+            let data: Vec<u8> = (0..data_area_size).map(|_| random::<u8>()).collect();
+            let mut dev_file = OpenOptions::new().write(true).open(dev_path).unwrap();
+            dev_file.write_all(&data).unwrap();
+
+            let image_path = PathBuf::from(format!("{}-image", file_path.display()));
+
+            for (hash_name, data_block_size, hash_block_size) in [
+                ("sha1", 4096, 4096),
+                ("sha224", 4096, 4096),
+                ("sha256", 4096, 4096),
+                ("sha256", 512, 1024),
+                ("sha384", 1024, 4096),
+                ("sha512", 4096, 4096),
+                ("sha3-256", 4096, 4096),
+            ] {
+                // Clear the hash area so that unwritten gaps compare equal.
+                dev_file.seek(SeekFrom::Start(data_area_size)).unwrap();
+                dev_file
+                    .write_all(&vec![0u8; data_area_size as usize])
+                    .unwrap();
+                dev_file.sync_all().unwrap();
+
+                let uuid = Uuid::new_v4();
+                let params = CryptParamsVerity {
+                    hash_name: hash_name.to_string(),
+                    data_device: dev_path.to_owned(),
+                    hash_device: None,
+                    fec_device: None,
+                    salt: random::<[u8; 32]>().to_vec(),
+                    hash_type: 1,
+                    data_block_size,
+                    hash_block_size,
+                    data_size: data_area_size / u64::from(data_block_size),
+                    hash_area_offset: data_area_size,
+                    fec_area_offset: 0,
+                    fec_roots: 0,
+                    flags: CryptVerity::CREATE_HASH,
+                };
+
+                let mut dev = CryptInit::init(dev_path).unwrap();
+                let mut params_ref: CryptParamsVerityRef<'_> = (&params).try_into().unwrap();
+                dev.context_handle()
+                    .format(
+                        EncryptionFormat::Verity,
+                        ("", ""),
+                        Some(uuid),
+                        Either::Right(0),
+                        Some(&mut params_ref),
+                    )
+                    .unwrap();
+                let root_hash = dev.verity_handle().root_hash().unwrap();
+
+                let tree = VerityHashTree::new(&params).unwrap();
+                tree.verify(&root_hash).unwrap();
+                let superblock =
+                    VerityHashTree::read_superblock(dev_path, dev_path, data_area_size).unwrap();
+                assert_eq!(superblock.hash_name, hash_name);
+                assert_eq!(superblock.salt, params.salt);
+                assert_eq!(superblock.data_size, params.data_size);
+
+                let mut image = File::create(&image_path).unwrap();
+                image.write_all(&data).unwrap();
+                let image_params = CryptParamsVerity {
+                    data_device: image_path.clone(),
+                    salt: params.salt.clone(),
+                    hash_name: params.hash_name.clone(),
+                    flags: CryptVerity::empty(),
+                    ..params
+                };
+                let image_tree = VerityHashTree::new(&image_params).unwrap();
+                assert_eq!(image_tree.create(Some(uuid)).unwrap(), root_hash);
+
+                let hash_area_size = image_tree.hash_area_size();
+                assert_eq!(
+                    read_range(&image_path, data_area_size, hash_area_size),
+                    read_range(dev_path, data_area_size, hash_area_size),
+                );
+                remove_file(&image_path).unwrap();
+            }
+        },
+    )
+}
@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::ptr;
+
+use crate::{
+    consts::{
+        flags::{CryptActivate, CryptActivateVerity, CryptVerity},
+        vals::EncryptionFormat,
+    },
+    device::{CryptDevice, CryptInit},
+    err::LibcryptErr,
+    format::{CryptParamsVerity, CryptParamsVerityRef},
+    Either,
+};
+
+/// Size in bytes of the salt generated when none is provided for a Verity format
+pub const DEFAULT_VERITY_SALT_SIZE: u32 = 32;
+
+/// Root hash and salt of a formatted Verity device
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CryptVerityRootHash {
+    /// Root hash of the hash tree
+    pub root_hash: Vec<u8>,
+    /// Salt used to compute the hash tree
+    pub salt: Vec<u8>,
+}
+
+/// Handle for Verity operations
+pub struct CryptVerityHandle<'a> {
+    reference: &'a mut CryptDevice,
+}
+
+impl<'a> CryptVerityHandle<'a> {
+    pub(crate) fn new(reference: &'a mut CryptDevice) -> Self {
+        CryptVerityHandle { reference }
+    }
+
+    /// Format the hash device and the optional FEC device for the data device
+    /// described by `params` and return the resulting root hash and salt.
+    ///
+    /// The device must have been initialized with the hash device. If the salt
+    /// in `params` is empty, a random salt of `DEFAULT_VERITY_SALT_SIZE` bytes
+    /// is generated. The hash area is always created.
+    pub fn format(
+        &mut self,
+        params: &CryptParamsVerity,
+    ) -> Result<CryptVerityRootHash, LibcryptErr> {
+        let mut params_ref: CryptParamsVerityRef<'_> = params.try_into()?;
+        params_ref.insert_flags(CryptVerity::CREATE_HASH);
+        if params.salt.is_empty() {
+            params_ref.generate_salt(DEFAULT_VERITY_SALT_SIZE);
+        }
+        self.reference.context_handle().format(
+            EncryptionFormat::Verity,
+            ("", ""),
+            None,
+            Either::Right(0),
+            Some(&mut params_ref),
+        )?;
+
+        let root_hash = self.root_hash()?;
+        let salt = self.reference.status_handle().get_verity_info()?.salt;
+        Ok(CryptVerityRootHash { root_hash, salt })
+    }
+
+    /// Load an existing Verity device described by `params`.
+    ///
+    /// The superblock is read from the hash device at the hash area offset. For
+    /// devices with the `NO_HEADER` flag, all parameters, including the salt,
+    /// are taken from `params` instead.
+    pub fn load(&mut self, params: &CryptParamsVerity) -> Result<(), LibcryptErr> {
+        let mut params_ref: CryptParamsVerityRef<'_> = params.try_into()?;
+        params_ref.remove_flags(CryptVerity::CREATE_HASH);
+        if params.flags.contains(CryptVerity::NO_HEADER) {
+            self.reference.context_handle().format(
+                EncryptionFormat::Verity,
+                ("", ""),
+                None,
+                Either::Right(0),
+                Some(&mut params_ref),
+            )
+        } else {
+            self.reference
+                .context_handle()
+                .load(Some(EncryptionFormat::Verity), Some(&mut params_ref))
+        }
+    }
+
+    /// Get the root hash of a device that was formatted with this context.
+    pub fn root_hash(&mut self) -> Result<Vec<u8>, LibcryptErr> {
+        let size = self.reference.status_handle().get_volume_key_size();
+        let mut root_hash = vec![0u8; errno_int_success!(size)? as usize];
+        let (_, size) = self
+            .reference
+            .volume_key_handle()
+            .get(None, &mut root_hash, None)?;
+        root_hash.truncate(size);
+        Ok(root_hash)
+    }
+
+    /// Verify every block of the data device against the hash tree and
+    /// `root_hash` without activating the device.
+    ///
+    /// The device must already have been formatted or loaded. Verification is
+    /// done with a separate context so the flags of this context are unchanged.
+    pub fn verify(&mut self, root_hash: &[u8]) -> Result<(), LibcryptErr> {
+        let mut params = self.reference.status_handle().get_verity_info()?;
+        params.flags = (params.flags | CryptVerity::CHECK_HASH) - CryptVerity::CREATE_HASH;
+
+        let mut device = CryptInit::init(
+            params
+                .hash_device
+                .as_deref()
+                .unwrap_or(params.data_device.as_path()),
+        )?;
+        CryptVerityHandle::new(&mut device).load(&params)?;
+        errno!(mutex!(libcryptsetup_rs_sys::crypt_activate_by_volume_key(
+            device.as_ptr(),
+            ptr::null(),
+            to_byte_ptr!(root_hash),
+            root_hash.len(),
+            0,
+        )))
+    }
+
+    /// Activate the device read-only with the given root hash.
+    pub fn activate(
+        &mut self,
+        name: &str,
+        root_hash: &[u8],
+        flags: CryptActivateVerity,
+    ) -> Result<(), LibcryptErr> {
+        self.reference.activate_handle().activate_by_volume_key(
+            Some(name),
+            Some(root_hash),
+            CryptActivate::from(flags | CryptActivateVerity::READONLY),
+        )
+    }
+}
@@ -0,0 +1,579 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::Path,
+    str::FromStr,
+};
+
+use digest::DynDigest;
+use uuid::Uuid;
+
+use crate::{
+    consts::flags::CryptVerity, err::LibcryptErr, format::CryptParamsVerity, hash::HashAlgorithm,
+};
+
+const VERITY_SIGNATURE: &[u8; 8] = b"verity\0\0";
+const VERITY_SB_SIZE: usize = 512;
+const VERITY_SB_VERSION: u32 = 1;
+const VERITY_MAX_HASH_TYPE: u32 = 1;
+const VERITY_MAX_SALT_SIZE: usize = 256;
+const VERITY_MAX_ALGORITHM_LEN: usize = 32;
+const VERITY_MIN_BLOCK_SIZE: u32 = 512;
+const VERITY_MAX_BLOCK_SIZE: u32 = 512 * 1024;
+
+/// Hash algorithms supported by the Rust implementation of the Verity hash tree
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VerityHashAlgorithm {
+    #[allow(missing_docs)]
+    Sha1,
+    #[allow(missing_docs)]
+    Sha224,
+    #[allow(missing_docs)]
+    Sha256,
+    #[allow(missing_docs)]
+    Sha384,
+    #[allow(missing_docs)]
+    Sha512,
+    #[allow(missing_docs)]
+    Sha3_224,
+    #[allow(missing_docs)]
+    Sha3_256,
+    #[allow(missing_docs)]
+    Sha3_384,
+    #[allow(missing_docs)]
+    Sha3_512,
+    #[allow(missing_docs)]
+    Ripemd160,
+}
+
+impl VerityHashAlgorithm {
+    /// Name of the hash algorithm as used by libcryptsetup and the kernel
+    pub fn name(&self) -> &'static str {
+        HashAlgorithm::from(*self).name()
+    }
+
+    /// Size in bytes of the digest produced by the hash algorithm
+    pub fn digest_size(&self) -> usize {
+        self.hasher().output_size()
+    }
+
+    pub(crate) fn hasher(&self) -> Box<dyn DynDigest> {
+        HashAlgorithm::from(*self).hasher()
+    }
+}
+
+impl From<VerityHashAlgorithm> for HashAlgorithm {
+    fn from(alg: VerityHashAlgorithm) -> Self {
+        match alg {
+            VerityHashAlgorithm::Sha1 => HashAlgorithm::Sha1,
+            VerityHashAlgorithm::Sha224 => HashAlgorithm::Sha224,
+            VerityHashAlgorithm::Sha256 => HashAlgorithm::Sha256,
+            VerityHashAlgorithm::Sha384 => HashAlgorithm::Sha384,
+            VerityHashAlgorithm::Sha512 => HashAlgorithm::Sha512,
+            VerityHashAlgorithm::Sha3_224 => HashAlgorithm::Sha3_224,
+            VerityHashAlgorithm::Sha3_256 => HashAlgorithm::Sha3_256,
+            VerityHashAlgorithm::Sha3_384 => HashAlgorithm::Sha3_384,
+            VerityHashAlgorithm::Sha3_512 => HashAlgorithm::Sha3_512,
+            VerityHashAlgorithm::Ripemd160 => HashAlgorithm::Ripemd160,
+        }
+    }
+}
+
+impl From<HashAlgorithm> for VerityHashAlgorithm {
+    fn from(alg: HashAlgorithm) -> Self {
+        match alg {
+            HashAlgorithm::Sha1 => VerityHashAlgorithm::Sha1,
+            HashAlgorithm::Sha224 => VerityHashAlgorithm::Sha224,
+            HashAlgorithm::Sha256 => VerityHashAlgorithm::Sha256,
+            HashAlgorithm::Sha384 => VerityHashAlgorithm::Sha384,
+            HashAlgorithm::Sha512 => VerityHashAlgorithm::Sha512,
+            HashAlgorithm::Sha3_224 => VerityHashAlgorithm::Sha3_224,
+            HashAlgorithm::Sha3_256 => VerityHashAlgorithm::Sha3_256,
+            HashAlgorithm::Sha3_384 => VerityHashAlgorithm::Sha3_384,
+            HashAlgorithm::Sha3_512 => VerityHashAlgorithm::Sha3_512,
+            HashAlgorithm::Ripemd160 => VerityHashAlgorithm::Ripemd160,
+        }
+    }
+}
+
+impl FromStr for VerityHashAlgorithm {
+    type Err = LibcryptErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HashAlgorithm::from_name(s)
+            .map(VerityHashAlgorithm::from)
+            .ok_or_else(|| LibcryptErr::Other(format!("Unsupported Verity hash algorithm {s}")))
+    }
+}
+
+/// Builder and verifier for dm-verity hash trees that operates directly on
+/// files and block devices without device-mapper.
+///
+/// The on-disk layout of the hash area and the superblock is identical to the
+/// one written by libcryptsetup for the same `CryptParamsVerity`. FEC is not
+/// supported.
+pub struct VerityHashTree<'a> {
+    params: &'a CryptParamsVerity,
+    algorithm: VerityHashAlgorithm,
+    data_blocks: u64,
+    digest_size: usize,
+    hash_per_block_bits: u32,
+    /// First hash block and number of hash blocks for each level, starting at
+    /// the level directly above the data blocks.
+    levels: Vec<(u64, u64)>,
+}
+
+impl<'a> VerityHashTree<'a> {
+    /// Validate `params` and compute the geometry of the hash tree.
+    ///
+    /// If the data size in `params` is zero, the size of the data device is used.
+    pub fn new(params: &'a CryptParamsVerity) -> Result<Self, LibcryptErr> {
+        let algorithm = VerityHashAlgorithm::from_str(&params.hash_name)?;
+        if params.hash_type > VERITY_MAX_HASH_TYPE {
+            return Err(LibcryptErr::Other(format!(
+                "Unsupported Verity hash type {}",
+                params.hash_type
+            )));
+        }
+        for block_size in [params.data_block_size, params.hash_block_size] {
+            if !block_size.is_power_of_two()
+                || !(VERITY_MIN_BLOCK_SIZE..=VERITY_MAX_BLOCK_SIZE).contains(&block_size)
+            {
+                return Err(LibcryptErr::Other(format!(
+                    "Invalid Verity block size {block_size}"
+                )));
+            }
+        }
+        if params.salt.len() > VERITY_MAX_SALT_SIZE {
+            return Err(LibcryptErr::Other(format!(
+                "Verity salt size {} exceeds {VERITY_MAX_SALT_SIZE} bytes",
+                params.salt.len()
+            )));
+        }
+        if params.fec_device.is_some() {
+            return Err(LibcryptErr::Other(
+                "Verity FEC is not supported".to_string(),
+            ));
+        }
+
+        let data_blocks = match params.data_size {
+            0 => {
+                File::open(&params.data_device)
+                    .and_then(|mut f| f.seek(SeekFrom::End(0)))
+                    .map_err(LibcryptErr::IOError)?
+                    / u64::from(params.data_block_size)
+            }
+            size => size,
+        };
+        if data_blocks == 0 {
+            return Err(LibcryptErr::Other(
+                "Verity data device is empty".to_string(),
+            ));
+        }
+
+        let digest_size = algorithm.digest_size();
+        let hash_per_block_bits = (params.hash_block_size as usize / digest_size)
+            .checked_ilog2()
+            .filter(|bits| *bits > 0)
+            .ok_or_else(|| {
+                LibcryptErr::Other(format!(
+                    "Verity hash block size {} is too small for {}",
+                    params.hash_block_size,
+                    algorithm.name()
+                ))
+            })?;
+
+        let mut level_count = 0;
+        while hash_per_block_bits * level_count < u64::BITS
+            && (data_blocks - 1) >> (hash_per_block_bits * level_count) != 0
+        {
+            level_count += 1;
+        }
+
+        let mut levels = vec![(0, 0); level_count as usize];
+        let mut hash_position = Self::hash_offset_block(params);
+        for (i, level) in levels.iter_mut().enumerate().rev() {
+            let shift = (i as u32 + 1) * hash_per_block_bits;
+            let size = if shift >= u64::BITS {
+                1
+            } else {
+                data_blocks.div_ceil(1 << shift)
+            };
+            *level = (hash_position, size);
+            hash_position += size;
+        }
+
+        let tree = VerityHashTree {
+            params,
+            algorithm,
+            data_blocks,
+            digest_size,
+            hash_per_block_bits,
+            levels,
+        };
+        if tree.hash_device() == params.data_device.as_path()
+            && tree.data_blocks * u64::from(params.data_block_size) > params.hash_area_offset
+        {
+            return Err(LibcryptErr::Other(
+                "Verity data area overlaps with hash area".to_string(),
+            ));
+        }
+        Ok(tree)
+    }
+
+    /// Number of data blocks covered by the hash tree
+    pub fn data_blocks(&self) -> u64 {
+        self.data_blocks
+    }
+
+    /// Size in bytes of the hash area, including the superblock if any
+    pub fn hash_area_size(&self) -> u64 {
+        let end_block = self
+            .levels
+            .first()
+            .map(|(start, size)| start + size)
+            .unwrap_or_else(|| Self::hash_offset_block(self.params));
+        end_block * u64::from(self.params.hash_block_size) - self.params.hash_area_offset
+    }
+
+    /// Write the hash tree and, unless the `NO_HEADER` flag is set, the
+    /// superblock to the hash device and return the root hash.
+    ///
+    /// A value of `None` for the UUID will generate a random UUID for the
+    /// superblock.
+    pub fn create(&self, uuid: Option<Uuid>) -> Result<Vec<u8>, LibcryptErr> {
+        let data = File::open(&self.params.data_device).map_err(LibcryptErr::IOError)?;
+        let hash = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.hash_device())
+            .map_err(LibcryptErr::IOError)?;
+        let root_hash = self.create_or_verify(&data, &hash, false)?;
+        if !self.params.flags.contains(CryptVerity::NO_HEADER) {
+            hash.write_all_at(
+                &self.superblock(uuid.unwrap_or_else(Uuid::new_v4))?,
+                self.params.hash_area_offset,
+            )
+            .map_err(LibcryptErr::IOError)?;
+        }
+        hash.sync_all().map_err(LibcryptErr::IOError)?;
+        Ok(root_hash)
+    }
+
+    /// Verify the data blocks and every hash block against `root_hash`.
+    pub fn verify(&self, root_hash: &[u8]) -> Result<(), LibcryptErr> {
+        let data = File::open(&self.params.data_device).map_err(LibcryptErr::IOError)?;
+        let hash = File::open(self.hash_device()).map_err(LibcryptErr::IOError)?;
+        if self.create_or_verify(&data, &hash, true)? != root_hash {
+            return Err(LibcryptErr::Other(
+                "Verity root hash does not match".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read the Verity superblock at `hash_area_offset` on the hash device and
+    /// return the parameters it describes for the given data device.
+    pub fn read_superblock(
+        data_device: &Path,
+        hash_device: &Path,
+        hash_area_offset: u64,
+    ) -> Result<CryptParamsVerity, LibcryptErr> {
+        let mut sb = [0u8; VERITY_SB_SIZE];
+        File::open(hash_device)
+            .and_then(|f| f.read_exact_at(&mut sb, hash_area_offset))
+            .map_err(LibcryptErr::IOError)?;
+        if &sb[0..8] != VERITY_SIGNATURE || le_u32(&sb[8..12]) != VERITY_SB_VERSION {
+            return Err(LibcryptErr::Other(format!(
+                "No Verity superblock found on {}",
+                hash_device.display()
+            )));
+        }
+        let algorithm = &sb[32..64];
+        let algorithm_len = algorithm
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(VERITY_MAX_ALGORITHM_LEN);
+        let salt_size = usize::from(u16::from_le_bytes([sb[80], sb[81]]));
+        if salt_size > VERITY_MAX_SALT_SIZE {
+            return Err(LibcryptErr::Other(format!(
+                "Verity salt size {salt_size} exceeds {VERITY_MAX_SALT_SIZE} bytes"
+            )));
+        }
+        Ok(CryptParamsVerity {
+            hash_name: std::str::from_utf8(&algorithm[..algorithm_len])
+                .map_err(LibcryptErr::Utf8Error)?
+                .to_string(),
+            data_device: data_device.to_owned(),
+            hash_device: Some(hash_device.to_owned()),
+            fec_device: None,
+            salt: sb[88..88 + salt_size].to_vec(),
+            hash_type: le_u32(&sb[12..16]),
+            data_block_size: le_u32(&sb[64..68]),
+            hash_block_size: le_u32(&sb[68..72]),
+            data_size: u64::from_le_bytes(sb[72..80].try_into().expect("8 byte slice")),
+            hash_area_offset,
+            fec_area_offset: 0,
+            fec_roots: 0,
+            flags: CryptVerity::empty(),
+        })
+    }
+
+    fn hash_offset_block(params: &CryptParamsVerity) -> u64 {
+        let hash_block_size = u64::from(params.hash_block_size);
+        if params.flags.contains(CryptVerity::NO_HEADER) {
+            params.hash_area_offset / hash_block_size
+        } else {
+            (params.hash_area_offset + VERITY_SB_SIZE as u64).div_ceil(hash_block_size)
+        }
+    }
+
+    fn hash_device(&self) -> &Path {
+        self.params
+            .hash_device
+            .as_deref()
+            .unwrap_or(self.params.data_device.as_path())
+    }
+
+    fn superblock(&self, uuid: Uuid) -> Result<[u8; VERITY_SB_SIZE], LibcryptErr> {
+        let algorithm = self.params.hash_name.as_bytes();
+        if algorithm.len() >= VERITY_MAX_ALGORITHM_LEN {
+            return Err(LibcryptErr::Other(format!(
+                "Verity hash algorithm name {} is too long",
+                self.params.hash_name
+            )));
+        }
+        let mut sb = [0u8; VERITY_SB_SIZE];
+        sb[0..8].copy_from_slice(VERITY_SIGNATURE);
+        sb[8..12].copy_from_slice(&VERITY_SB_VERSION.to_le_bytes());
+        sb[12..16].copy_from_slice(&self.params.hash_type.to_le_bytes());
+        sb[16..32].copy_from_slice(uuid.as_bytes());
+        sb[32..32 + algorithm.len()].copy_from_slice(algorithm);
+        sb[64..68].copy_from_slice(&self.params.data_block_size.to_le_bytes());
+        sb[68..72].copy_from_slice(&self.params.hash_block_size.to_le_bytes());
+        sb[72..80].copy_from_slice(&self.data_blocks.to_le_bytes());
+        sb[80..82].copy_from_slice(&(self.params.salt.len() as u16).to_le_bytes());
+        sb[88..88 + self.params.salt.len()].copy_from_slice(&self.params.salt);
+        Ok(sb)
+    }
+
+    fn create_or_verify(
+        &self,
+        data: &File,
+        hash: &File,
+        verify: bool,
+    ) -> Result<Vec<u8>, LibcryptErr> {
+        let data_block_size = self.params.data_block_size;
+        let hash_block_size = self.params.hash_block_size;
+        let mut input_blocks = self.data_blocks;
+        for (i, (start, size)) in self.levels.iter().enumerate() {
+            if i == 0 {
+                self.hash_level(
+                    data,
+                    0,
+                    data_block_size,
+                    Some((hash, *start)),
+                    input_blocks,
+                    verify,
+                )?;
+            } else {
+                let (previous_start, _) = self.levels[i - 1];
+                self.hash_level(
+                    hash,
+                    previous_start,
+                    hash_block_size,
+                    Some((hash, *start)),
+                    input_blocks,
+                    verify,
+                )?;
+            }
+            input_blocks = *size;
+        }
+        match self.levels.last() {
+            Some((start, _)) => self.hash_level(hash, *start, hash_block_size, None, 1, verify),
+            None => self.hash_level(data, 0, data_block_size, None, 1, verify),
+        }
+    }
+
+    /// Hash `blocks` input blocks and write or compare the digests to the hash
+    /// blocks starting at the output block. Without an output, the digest of
+    /// the first input block is returned.
+    fn hash_level(
+        &self,
+        input: &File,
+        input_block: u64,
+        input_block_size: u32,
+        output: Option<(&File, u64)>,
+        blocks: u64,
+        verify: bool,
+    ) -> Result<Vec<u8>, LibcryptErr> {
+        let hash_block_size = self.params.hash_block_size as usize;
+        let hash_per_block = 1u64 << self.hash_per_block_bits;
+        let digest_size_full = if self.params.hash_type == 0 {
+            self.digest_size
+        } else {
+            self.digest_size.next_power_of_two()
+        };
+
+        let mut hasher = self.algorithm.hasher();
+        let mut block = vec![0u8; input_block_size as usize];
+        let mut digest = vec![0u8; self.digest_size];
+        let mut hash_block = vec![0u8; hash_block_size];
+        let mut stored_block = vec![0u8; hash_block_size];
+        let mut input_offset = input_block * u64::from(input_block_size);
+        let mut blocks_left = blocks;
+
+        for output_index in 0..blocks.div_ceil(hash_per_block) {
+            hash_block.fill(0);
+            let mut position = 0;
+            for _ in 0..hash_per_block {
+                if blocks_left == 0 {
+                    break;
+                }
+                blocks_left -= 1;
+                input
+                    .read_exact_at(&mut block, input_offset)
+                    .map_err(LibcryptErr::IOError)?;
+                input_offset += u64::from(input_block_size);
+
+                if self.params.hash_type == 0 {
+                    hasher.update(&block);
+                    hasher.update(&self.params.salt);
+                } else {
+                    hasher.update(&self.params.salt);
+                    hasher.update(&block);
+                }
+                hasher
+                    .finalize_into_reset(&mut digest)
+                    .map_err(|_| LibcryptErr::InvalidConversion)?;
+
+                if output.is_none() {
+                    return Ok(digest);
+                }
+                hash_block[position..position + self.digest_size].copy_from_slice(&digest);
+                position += digest_size_full;
+            }
+
+            if let Some((file, output_block)) = output {
+                let offset = (output_block + output_index) * hash_block_size as u64;
+                if verify {
+                    file.read_exact_at(&mut stored_block, offset)
+                        .map_err(LibcryptErr::IOError)?;
+                    if stored_block != hash_block {
+                        return Err(LibcryptErr::Other(format!(
+                            "Verification of Verity hash block {} failed",
+                            output_block + output_index
+                        )));
+                    }
+                } else {
+                    file.write_all_at(&hash_block, offset)
+                        .map_err(LibcryptErr::IOError)?;
+                }
+            }
+        }
+        Ok(digest)
+    }
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().expect("4 byte slice"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{env, fs::remove_file, io::Write, path::PathBuf};
+
+    fn temp_file(name: &str, size: usize) -> PathBuf {
+        let path = env::temp_dir().join(format!("libcryptsetup-rs-{name}-{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        file.write_all(&contents).unwrap();
+        path
+    }
+
+    fn params(data: &Path, hash: &Path, hash_name: &str) -> CryptParamsVerity {
+        CryptParamsVerity {
+            hash_name: hash_name.to_string(),
+            data_device: data.to_owned(),
+            hash_device: Some(hash.to_owned()),
+            fec_device: None,
+            salt: vec![0xab; 32],
+            hash_type: 1,
+            data_block_size: 4096,
+            hash_block_size: 4096,
+            data_size: 0,
+            hash_area_offset: 0,
+            fec_area_offset: 0,
+            fec_roots: 0,
+            flags: CryptVerity::empty(),
+        }
+    }
+
+    #[test]
+    fn test_verity_hash_algorithm_names() {
+        for name in ["sha1", "sha256", "SHA512", "sha3-256", "ripemd160"] {
+            let alg = VerityHashAlgorithm::from_str(name).unwrap();
+            assert_eq!(alg.name(), name.to_lowercase());
+        }
+        assert_eq!(VerityHashAlgorithm::Sha1.digest_size(), 20);
+        assert_eq!(VerityHashAlgorithm::Sha384.digest_size(), 48);
+        assert!(VerityHashAlgorithm::from_str("whirlpool").is_err());
+    }
+
+    #[test]
+    fn test_verity_hash_tree_geometry() {
+        let data = temp_file("geometry-data", 4096 * 200);
+        let hash = temp_file("geometry-hash", 0);
+        let params = params(&data, &hash, "sha256");
+        let tree = VerityHashTree::new(&params).unwrap();
+        assert_eq!(tree.data_blocks(), 200);
+        // 128 digests per hash block: 2 blocks at level 0, 1 block at level 1
+        // and one block for the superblock.
+        assert_eq!(tree.levels, vec![(2, 2), (1, 1)]);
+        assert_eq!(tree.hash_area_size(), 4 * 4096);
+        remove_file(data).unwrap();
+        remove_file(hash).unwrap();
+    }
+
+    #[test]
+    fn test_verity_hash_tree_create_verify() {
+        let data = temp_file("create-data", 4096 * 300);
+        let hash = temp_file("create-hash", 0);
+        let params = params(&data, &hash, "sha1");
+        let tree = VerityHashTree::new(&params).unwrap();
+        let root_hash = tree.create(None).unwrap();
+        assert_eq!(root_hash.len(), 20);
+        tree.verify(&root_hash).unwrap();
+
+        let read = VerityHashTree::read_superblock(&data, &hash, 0).unwrap();
+        assert_eq!(read.hash_name, "sha1");
+        assert_eq!(read.salt, params.salt);
+        assert_eq!(read.data_size, 300);
+        VerityHashTree::new(&read)
+            .unwrap()
+            .verify(&root_hash)
+            .unwrap();
+
+        let mut wrong_root_hash = root_hash.clone();
+        wrong_root_hash[0] ^= 0xff;
+        assert!(tree.verify(&wrong_root_hash).is_err());
+
+        OpenOptions::new()
+            .write(true)
+            .open(&data)
+            .unwrap()
+            .write_all_at(&[0xff; 16], 4096 * 150)
+            .unwrap();
+        assert!(tree.verify(&root_hash).is_err());
+
+        remove_file(data).unwrap();
+        remove_file(hash).unwrap();
+    }
+}
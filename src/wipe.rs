@@ -3,11 +3,13 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
+    ops::ControlFlow,
     os::raw::{c_int, c_void},
     path::Path,
 };
 
 use crate::{
+    callback::{progress_trampoline, ProgressState},
     consts::{flags::CryptWipe, vals::CryptWipePattern},
     device::CryptDevice,
     err::LibcryptErr,
@@ -55,4 +57,41 @@ impl<'a> CryptWipeHandle<'a> {
             },
         )))
     }
+
+    /// Wipe a device with the selected pattern, reporting progress to a
+    /// closure.
+    ///
+    /// The closure receives the total size and the number of bytes wiped so
+    /// far. Returning `ControlFlow::Break` aborts the wipe. A panic in the
+    /// closure also aborts the wipe and is resumed once libcryptsetup has
+    /// returned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn wipe_with_progress<F>(
+        &mut self,
+        dev_path: &Path,
+        pattern: CryptWipePattern,
+        offset: u64,
+        length: u64,
+        wipe_block_size: crate::size_t,
+        flags: CryptWipe,
+        mut progress: F,
+    ) -> Result<(), LibcryptErr>
+    where
+        F: FnMut(u64, u64) -> ControlFlow<()>,
+    {
+        let dev_path_cstring = path_to_cstring!(dev_path)?;
+        let mut state = ProgressState::new(&mut progress);
+        let rc = mutex!(libcryptsetup_rs_sys::crypt_wipe(
+            self.reference.as_ptr(),
+            dev_path_cstring.as_ptr(),
+            pattern.into(),
+            offset,
+            length,
+            wipe_block_size,
+            flags.bits(),
+            Some(progress_trampoline),
+            state.as_ptr(),
+        ));
+        errno!(state.finish(rc))
+    }
 }